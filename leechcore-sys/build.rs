@@ -56,6 +56,7 @@ fn build() {
                 .map(|o| "src/leechcore/leechcore/".to_string() + o)
                 .collect::<Vec<_>>(),
         )
+        .file("src/leechcore_printf.c")
         .include("src/leechcore/includes/")
         .flag(&format!("-D{}", os_define()))
         .flag("-D_GNU_SOURCE");
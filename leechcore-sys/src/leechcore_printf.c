@@ -0,0 +1,29 @@
+// printf replacement for LeechCore that forwards formatted messages to a callback.
+// Rust is unable to define C variadic functions, so the formatting has to happen here.
+#include <stdarg.h>
+#include <stdio.h>
+
+typedef void (*leechcore_printf_cb_t)(const char *msg);
+
+static leechcore_printf_cb_t leechcore_printf_cb = NULL;
+
+void leechcore_set_printf_callback(leechcore_printf_cb_t cb)
+{
+    leechcore_printf_cb = cb;
+}
+
+int leechcore_printf(const char *format, ...)
+{
+    char buffer[4096];
+    va_list args;
+    int len;
+
+    va_start(args, format);
+    len = vsnprintf(buffer, sizeof(buffer), format, args);
+    va_end(args);
+
+    if (len >= 0 && leechcore_printf_cb) {
+        leechcore_printf_cb(buffer);
+    }
+    return len;
+}
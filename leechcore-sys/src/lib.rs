@@ -10,6 +10,19 @@
 // will be written by build.rs
 include!(concat!(env!("OUT_DIR"), "/leechcore.rs"));
 
+// see leechcore_printf.c
+extern "C" {
+    /// printf compatible function that can be passed as `pfn_printf_opt` in `LC_CONFIG`.
+    ///
+    /// Formatted messages are forwarded to the callback set via `leechcore_set_printf_callback`.
+    pub fn leechcore_printf(format: *const ::std::os::raw::c_char, ...) -> ::std::os::raw::c_int;
+
+    /// Sets the callback that receives all messages formatted by `leechcore_printf`.
+    pub fn leechcore_set_printf_callback(
+        cb: ::std::option::Option<unsafe extern "C" fn(msg: *const ::std::os::raw::c_char)>,
+    );
+}
+
 #[cfg(target_os = "windows")]
 use ctor::{ctor, dtor};
 
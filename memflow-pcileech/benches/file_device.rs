@@ -0,0 +1,146 @@
+/*!
+Benchmarks the connector against the LeechCore `file` device backed by a temporary memory dump.
+
+Compare runs via criterion baselines, e.g. `cargo bench -- --save-baseline before`
+and `cargo bench -- --baseline before`.
+*/
+use std::path::PathBuf;
+
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+
+use memflow::prelude::v1::*;
+use memflow_pcileech::PciLeech;
+
+const DUMP_SIZE: usize = 0x100000;
+const PAGE_SIZE: usize = 0x1000;
+
+/// Creates a dump file filled with a known pattern and returns its path.
+fn create_dump(name: &str) -> PathBuf {
+    let path = std::env::temp_dir().join(format!("memflow-pcileech-bench-{name}.raw"));
+    let data = (0..DUMP_SIZE).map(|i| (i % 251) as u8).collect::<Vec<_>>();
+    std::fs::write(&path, data).expect("unable to create dump file");
+    path
+}
+
+/// Many small reads spread across the dump, similar to the page table walks of an os layer.
+fn small_reads(c: &mut Criterion) {
+    let path = create_dump("small-reads");
+    let mut conn = PciLeech::new(&format!("file://{}", path.display()), None, false)
+        .expect("unable to open file device");
+
+    let mut group = c.benchmark_group("small_reads");
+    for &count in [1usize, 16, 256, 4096].iter() {
+        let mut bufs = vec![[0u8; 8]; count];
+        group.throughput(Throughput::Elements(count as u64));
+        group.bench_with_input(BenchmarkId::from_parameter(count), &count, |b, _| {
+            b.iter(|| {
+                let mut reads = bufs
+                    .iter_mut()
+                    .enumerate()
+                    .map(|(i, buf)| {
+                        let addr = (i * 0x238 + 4) % (DUMP_SIZE - 8);
+                        CTup2(Address::from(addr as umem), CSliceMut::from(&mut buf[..]))
+                    })
+                    .collect::<Vec<_>>();
+                conn.phys_view()
+                    .read_raw_list(&mut reads)
+                    .expect("unable to read physical memory");
+            })
+        });
+    }
+    group.finish();
+}
+
+/// Small unaligned writes which require a read-modify-write cycle.
+fn small_writes(c: &mut Criterion) {
+    let path = create_dump("small-writes");
+    let mut conn = PciLeech::new(
+        &format!("file://file={},write=1", path.display()),
+        None,
+        false,
+    )
+    .expect("unable to open file device");
+
+    let mut group = c.benchmark_group("small_writes");
+    for &count in [1usize, 16, 256].iter() {
+        let buf = [0x55u8; 4];
+        group.throughput(Throughput::Elements(count as u64));
+        group.bench_with_input(BenchmarkId::from_parameter(count), &count, |b, &count| {
+            b.iter(|| {
+                let writes = (0..count)
+                    .map(|i| {
+                        let addr = (i * 0x238 + 2) % (DUMP_SIZE - 8);
+                        CTup2(Address::from(addr as umem), CSliceRef::from(&buf[..]))
+                    })
+                    .collect::<Vec<_>>();
+                conn.phys_view()
+                    .write_raw_list(&writes)
+                    .expect("unable to write physical memory");
+            })
+        });
+    }
+    group.finish();
+}
+
+/// Large contiguous reads as issued by memory dumping tools.
+///
+/// Compares the bulk path via `LcRead` against one scatter entry per page.
+fn contiguous_reads(c: &mut Criterion) {
+    let path = create_dump("contiguous-reads");
+    let mut conn = PciLeech::new(&format!("file://{}", path.display()), None, false)
+        .expect("unable to open file device");
+
+    let mut group = c.benchmark_group("contiguous_reads");
+    for &pages in [1usize, 16, 256].iter() {
+        let mut buf = vec![0u8; pages * PAGE_SIZE];
+        group.throughput(Throughput::Bytes(buf.len() as u64));
+        for &(name, bulk_min_pages) in [("scatter", None), ("bulk", Some(1))].iter() {
+            conn.set_bulk_min_pages(bulk_min_pages);
+            group.bench_with_input(BenchmarkId::new(name, pages), &pages, |b, _| {
+                b.iter(|| {
+                    conn.phys_view()
+                        .read_raw_into(Address::from(0x1000), &mut buf)
+                        .expect("unable to read physical memory");
+                })
+            });
+        }
+    }
+    group.finish();
+}
+
+/// Large contiguous writes, comparing the bulk path via `LcWrite` against one scatter entry per page.
+fn contiguous_writes(c: &mut Criterion) {
+    let path = create_dump("contiguous-writes");
+    let mut conn = PciLeech::new(
+        &format!("file://file={},write=1", path.display()),
+        None,
+        false,
+    )
+    .expect("unable to open file device");
+
+    let mut group = c.benchmark_group("contiguous_writes");
+    for &pages in [1usize, 16, 256].iter() {
+        let buf = vec![0x55u8; pages * PAGE_SIZE];
+        group.throughput(Throughput::Bytes(buf.len() as u64));
+        for &(name, bulk_min_pages) in [("scatter", None), ("bulk", Some(1))].iter() {
+            conn.set_bulk_min_pages(bulk_min_pages);
+            group.bench_with_input(BenchmarkId::new(name, pages), &pages, |b, _| {
+                b.iter(|| {
+                    conn.phys_view()
+                        .write_raw(Address::from(0x1000), &buf)
+                        .expect("unable to write physical memory");
+                })
+            });
+        }
+    }
+    group.finish();
+}
+
+criterion_group!(
+    benches,
+    small_reads,
+    small_writes,
+    contiguous_reads,
+    contiguous_writes
+);
+criterion_main!(benches);
@@ -33,8 +33,23 @@ fn main() {
     let connector = memflow_pcileech::create_connector(&connector_args)
         .expect("unable to create pcileech connector");
 
-    let mut os = Win32Kernel::builder(connector)
-        .build_default_caches()
+    // dump files might already contain the dtb and kernel base which saves scanning for them
+    let memory_info = connector
+        .memory_info()
+        .expect("unable to query memory info");
+
+    let mut builder = Win32Kernel::builder(connector).build_default_caches();
+    if let Some(memory_info) = memory_info {
+        info!("{:?}", memory_info);
+        if let Some(dtb) = memory_info.dtb {
+            builder = builder.dtb(dtb);
+        }
+        if let Some(kernel_base) = memory_info.kernel_base {
+            builder = builder.kernel_hint(kernel_base);
+        }
+    }
+
+    let mut os = builder
         .build()
         .expect("unable to create win32 instance with pcileech connector");
 
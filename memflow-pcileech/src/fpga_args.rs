@@ -0,0 +1,169 @@
+//! Connector arguments for tuning the timing and read algorithms of FPGA devices.
+use std::ops::RangeInclusive;
+
+use log::{info, warn};
+
+use memflow::prelude::v1::*;
+
+use crate::handle::LcHandle;
+use crate::option::{LcOption, LcOptionKind, LcOptionValue};
+
+/// A connector argument that is applied to an FPGA option via `LcSetOption`.
+pub(crate) struct FpgaArg {
+    pub name: &'static str,
+    pub option: LcOption,
+    description: &'static str,
+    /// The accepted values, bool options accept `0` and `1` as well as `false` and `true`.
+    range: RangeInclusive<u64>,
+}
+
+// delays are specified in microseconds
+const MAX_DELAY: u64 = 1_000_000;
+
+pub(crate) const FPGA_ARGS: &[FpgaArg] = &[
+    FpgaArg {
+        name: "fpga-delay-read",
+        option: LcOption::FpgaDelayRead,
+        description: "delay in microseconds after each read request to the FPGA (0 - 1000000)",
+        range: 0..=MAX_DELAY,
+    },
+    FpgaArg {
+        name: "fpga-delay-write",
+        option: LcOption::FpgaDelayWrite,
+        description: "delay in microseconds after each write request to the FPGA (0 - 1000000)",
+        range: 0..=MAX_DELAY,
+    },
+    FpgaArg {
+        name: "fpga-delay-probe-read",
+        option: LcOption::FpgaDelayProbeRead,
+        description: "delay in microseconds after each probe read request to the FPGA (0 - 1000000)",
+        range: 0..=MAX_DELAY,
+    },
+    FpgaArg {
+        name: "fpga-delay-probe-write",
+        option: LcOption::FpgaDelayProbeWrite,
+        description: "delay in microseconds after each probe write request to the FPGA (0 - 1000000)",
+        range: 0..=MAX_DELAY,
+    },
+    FpgaArg {
+        name: "fpga-retry-on-error",
+        option: LcOption::FpgaRetryOnError,
+        description: "retry failed reads once inside LeechCore (0 or 1)",
+        range: 0..=1,
+    },
+    FpgaArg {
+        name: "fpga-algo-tiny",
+        option: LcOption::FpgaAlgoTiny,
+        description: "use the tiny read algorithm with 128 bytes per TLP (0 or 1)",
+        range: 0..=1,
+    },
+    FpgaArg {
+        name: "fpga-algo-synchronous",
+        option: LcOption::FpgaAlgoSynchronous,
+        description: "use the synchronous (old) read algorithm (0 or 1)",
+        range: 0..=1,
+    },
+    FpgaArg {
+        name: "fpga-max-size-rx",
+        option: LcOption::FpgaMaxSizeRx,
+        description: "maximum number of bytes received from the FPGA in a single transfer (0x1000 - 0xffffffff)",
+        range: 0x1000..=u32::MAX as u64,
+    },
+    FpgaArg {
+        name: "fpga-max-size-tx",
+        option: LcOption::FpgaMaxSizeTx,
+        description: "maximum number of bytes sent to the FPGA in a single transfer (1 - 0xffffffff)",
+        range: 1..=u32::MAX as u64,
+    },
+];
+
+impl FpgaArg {
+    /// Returns the descriptor of this argument for the `ArgsValidator`.
+    pub fn descriptor(&self) -> ArgDescriptor {
+        let kind = self.option.kind();
+        let range = self.range.clone();
+        ArgDescriptor::new(self.name)
+            .description(self.description)
+            .validator(Box::new(move |value| {
+                parse(kind, &range, value).map(|_| ())
+            }))
+    }
+
+    /// Parses and validates the value of this argument.
+    pub fn parse(&self, value: &str) -> std::result::Result<LcOptionValue, &'static str> {
+        parse(self.option.kind(), &self.range, value)
+    }
+}
+
+fn parse(
+    kind: LcOptionKind,
+    range: &RangeInclusive<u64>,
+    value: &str,
+) -> std::result::Result<LcOptionValue, &'static str> {
+    let value = match (kind, value.trim()) {
+        (LcOptionKind::Bool, "true") => 1,
+        (LcOptionKind::Bool, "false") => 0,
+        (_, value) => match value.strip_prefix("0x") {
+            Some(hex) => u64::from_str_radix(hex, 16),
+            None => value.parse::<u64>(),
+        }
+        .map_err(|_| "expected a decimal or 0x prefixed hexadecimal number")?,
+    };
+
+    if !range.contains(&value) {
+        return Err("value is out of range");
+    }
+
+    Ok(match kind {
+        LcOptionKind::Bool => LcOptionValue::Bool(value != 0),
+        _ => LcOptionValue::U64(value),
+    })
+}
+
+/// Applies all FPGA arguments that are set and logs the values in effect afterwards.
+///
+/// Returns true if any option was changed.
+pub(crate) fn apply(handle: &LcHandle, args: &Args) -> Result<bool> {
+    let mut changed = false;
+    for arg in FPGA_ARGS {
+        if let Some(value) = args.get(arg.name) {
+            let value = arg.parse(value).map_err(|err| {
+                Error(ErrorOrigin::Connector, ErrorKind::ArgValidation)
+                    .log_error(format!("invalid '{}' argument: {}", arg.name, err))
+            })?;
+            arg.option.set(handle, value)?;
+            changed = true;
+
+            // leechcore might adjust the value to what the device supports
+            let effective = arg.option.get(handle)?;
+            if effective != value {
+                warn!(
+                    "'{}' was set to {} instead of {}",
+                    arg.name,
+                    format_value(effective),
+                    format_value(value)
+                );
+            }
+        }
+    }
+
+    if changed {
+        for arg in FPGA_ARGS {
+            if let Ok(value) = arg.option.get(handle) {
+                info!("{} = {}", arg.name, format_value(value));
+            }
+        }
+    }
+
+    Ok(changed)
+}
+
+fn format_value(value: LcOptionValue) -> String {
+    match value {
+        LcOptionValue::Bool(value) => (value as u8).to_string(),
+        LcOptionValue::U64(value) => value.to_string(),
+        LcOptionValue::Version(version) => {
+            format!("{}.{}.{}", version.major, version.minor, version.revision)
+        }
+    }
+}
@@ -0,0 +1,261 @@
+use std::ffi::c_void;
+use std::fmt;
+use std::ptr;
+use std::slice;
+
+use memflow::prelude::v1::{Error, ErrorKind, ErrorOrigin};
+
+use leechcore_sys::*;
+
+use crate::printf;
+
+/// Owned LeechCore handle.
+///
+/// The underlying device is closed via `LcClose` when this object is dropped.
+pub struct LcHandle(HANDLE);
+
+// LeechCore handles are not bound to the thread that created them.
+unsafe impl Send for LcHandle {}
+
+impl LcHandle {
+    /// Opens the LeechCore device with the same config the connector uses.
+    ///
+    /// `device` and `remote` are passed to LeechCore as is, e.g. `fpga` or `file://dump.raw`.
+    pub fn open(device: &str, remote: Option<&str>) -> std::result::Result<Self, LcCreateError> {
+        let mut conf = crate::build_lc_config(device, remote, false);
+        Self::create(&mut conf)
+    }
+
+    /// Opens a new LeechCore device with the given config.
+    ///
+    /// In case the device could not be opened the error reported by LeechCore is returned.
+    /// The messages LeechCore printed while opening the device are used as the error message
+    /// unless LeechCore provided a message of its own.
+    pub fn create(conf: &mut LC_CONFIG) -> std::result::Result<Self, LcCreateError> {
+        let ((handle, p_err_info), output) = printf::capture(|| {
+            let mut p_err_info: PLC_CONFIG_ERRORINFO = ptr::null_mut();
+            let handle = unsafe { LcCreateEx(conf, &mut p_err_info) };
+            (handle, p_err_info)
+        });
+
+        // leechcore might return error info even on success, it is freed in either case
+        let err_info = LcErrorInfo::from_raw(p_err_info);
+        if !handle.is_null() {
+            return Ok(Self(handle));
+        }
+
+        Err(match err_info {
+            Some(err_info) if err_info.version() != LC_CONFIG_ERRORINFO_VERSION => {
+                LcCreateError::VersionMismatch(format!(
+                    "error info version {:#x} does not match leechcore version {:#x}",
+                    err_info.version(),
+                    LC_CONFIG_ERRORINFO_VERSION
+                ))
+            }
+            Some(err_info) if err_info.user_input_request() => {
+                LcCreateError::UserInputRequest(err_info.user_text())
+            }
+            // leechcore rejects a config of a different version before anything is printed
+            None if output.is_empty() => LcCreateError::VersionMismatch(format!(
+                "leechcore rejected the config without a reason, config version {:#x} might not be supported",
+                conf.dwVersion
+            )),
+            err_info => {
+                // leechcore only reports error info in special cases,
+                // the reason for all other failures is only printed
+                let message = err_info
+                    .map(|err_info| err_info.user_text())
+                    .filter(|text| !text.is_empty())
+                    .unwrap_or_else(|| output.join("\n"));
+                if device_not_found(&message) {
+                    LcCreateError::DeviceNotFound(message)
+                } else {
+                    LcCreateError::CreateFailed(message)
+                }
+            }
+        })
+    }
+
+    /// Returns the raw LeechCore handle.
+    ///
+    /// The returned handle is only valid as long as this object is alive.
+    pub fn as_raw(&self) -> HANDLE {
+        self.0
+    }
+
+    /// Reads the raw `LC_OPT_*` option, returns `None` if the option could not be read.
+    pub fn get_option(&self, option: u64) -> Option<u64> {
+        let mut value = 0;
+        if unsafe { LcGetOption(self.0, option, &mut value) } != 0 {
+            Some(value)
+        } else {
+            None
+        }
+    }
+
+    /// Writes the raw `LC_OPT_*` option, returns `false` if the option could not be written.
+    pub fn set_option(&self, option: u64, value: u64) -> bool {
+        unsafe { LcSetOption(self.0, option, value) != 0 }
+    }
+
+    /// Executes the raw `LC_CMD_*` command, returns `None` if the command failed.
+    ///
+    /// The output data of the command is copied into the returned buffer.
+    pub fn command(&self, command: u64, data_in: &[u8]) -> Option<Vec<u8>> {
+        let mut pb_out: PBYTE = ptr::null_mut();
+        let mut cb_out: DWORD = 0;
+        let result = unsafe {
+            LcCommand(
+                self.0,
+                command,
+                data_in.len() as u32,
+                data_in.as_ptr() as PBYTE,
+                &mut pb_out,
+                &mut cb_out,
+            )
+        };
+
+        let data_out = if pb_out.is_null() {
+            Vec::new()
+        } else {
+            let data_out = unsafe { slice::from_raw_parts(pb_out, cb_out as usize) }.to_vec();
+            unsafe { LcMemFree(pb_out as *mut c_void) };
+            data_out
+        };
+
+        if result != 0 {
+            Some(data_out)
+        } else {
+            None
+        }
+    }
+}
+
+/// Returns true if the failure message of LeechCore indicates that the device could not be found or opened.
+fn device_not_found(message: &str) -> bool {
+    let message = message.to_ascii_lowercase();
+    ["not found", "no such", "open", "connect"]
+        .iter()
+        .any(|keyword| message.contains(keyword))
+}
+
+impl Drop for LcHandle {
+    fn drop(&mut self) {
+        unsafe { LcClose(self.0) };
+    }
+}
+
+/// Owned error info as returned by `LcCreateEx`.
+///
+/// The underlying buffer is freed via `LcMemFree` when this object is dropped.
+pub struct LcErrorInfo(PLC_CONFIG_ERRORINFO);
+
+impl LcErrorInfo {
+    fn from_raw(p_err_info: PLC_CONFIG_ERRORINFO) -> Option<Self> {
+        if p_err_info.is_null() {
+            None
+        } else {
+            Some(Self(p_err_info))
+        }
+    }
+
+    fn raw(&self) -> &LC_CONFIG_ERRORINFO {
+        unsafe { &*self.0 }
+    }
+
+    /// Returns the version of the error info struct.
+    pub fn version(&self) -> u32 {
+        self.raw().dwVersion
+    }
+
+    /// Returns true if LeechCore requested additional user input.
+    pub fn user_input_request(&self) -> bool {
+        self.raw().fUserInputRequest != 0
+    }
+
+    /// Returns the decoded message that LeechCore wants to show to the user.
+    pub fn user_text(&self) -> String {
+        let raw = self.raw();
+        let text = unsafe { raw.wszUserText.as_slice(raw.cwszUserText as usize) };
+        String::from_utf16_lossy(text)
+            .trim_end_matches('\0')
+            .trim()
+            .to_string()
+    }
+}
+
+impl Drop for LcErrorInfo {
+    fn drop(&mut self) {
+        unsafe { LcMemFree(self.0 as *mut c_void) };
+    }
+}
+
+impl fmt::Debug for LcErrorInfo {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let raw = self.raw();
+        f.debug_struct("LcErrorInfo")
+            .field("dwVersion", &raw.dwVersion)
+            .field("cbStruct", &raw.cbStruct)
+            .field("fUserInputRequest", &raw.fUserInputRequest)
+            .field("cwszUserText", &raw.cwszUserText)
+            .field("wszUserText", &self.user_text())
+            .finish()
+    }
+}
+
+/// Error returned when a LeechCore device could not be created.
+///
+/// The contained string is the message reported or printed by LeechCore and might be empty.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum LcCreateError {
+    /// LeechCore requires additional input from the user, e.g. a password for a remote connection.
+    UserInputRequest(String),
+    /// The config or error info version does not match the linked LeechCore library.
+    VersionMismatch(String),
+    /// The device could not be found or opened.
+    DeviceNotFound(String),
+    /// The device could not be created for any other reason.
+    CreateFailed(String),
+}
+
+impl LcCreateError {
+    /// Returns the message reported by LeechCore.
+    pub fn message(&self) -> &str {
+        match self {
+            LcCreateError::UserInputRequest(msg)
+            | LcCreateError::VersionMismatch(msg)
+            | LcCreateError::DeviceNotFound(msg)
+            | LcCreateError::CreateFailed(msg) => msg,
+        }
+    }
+}
+
+impl fmt::Display for LcCreateError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let kind = match self {
+            LcCreateError::UserInputRequest(_) => "leechcore requested user input",
+            LcCreateError::VersionMismatch(_) => "leechcore version mismatch",
+            LcCreateError::DeviceNotFound(_) => "leechcore device not found",
+            LcCreateError::CreateFailed(_) => "unable to create leechcore device",
+        };
+        if self.message().is_empty() {
+            f.write_str(kind)
+        } else {
+            write!(f, "{}: {}", kind, self.message())
+        }
+    }
+}
+
+impl std::error::Error for LcCreateError {}
+
+impl From<LcCreateError> for Error {
+    fn from(err: LcCreateError) -> Self {
+        let kind = match err {
+            LcCreateError::UserInputRequest(_) => ErrorKind::Configuration,
+            LcCreateError::VersionMismatch(_) => ErrorKind::VersionMismatch,
+            LcCreateError::DeviceNotFound(_) => ErrorKind::NotFound,
+            LcCreateError::CreateFailed(_) => ErrorKind::Configuration,
+        };
+        Error(ErrorOrigin::Connector, kind)
+    }
+}
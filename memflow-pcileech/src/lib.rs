@@ -1,14 +1,12 @@
 use parking_lot::Mutex;
-use std::ffi::c_void;
+use std::ops::Deref;
 use std::os::raw::c_char;
-use std::path::Path;
-use std::ptr;
+use std::path::{Path, PathBuf};
 use std::ptr::null_mut;
-use std::slice;
 use std::sync::Arc;
+use std::time::Duration;
 
-use log::LevelFilter;
-use log::{error, info};
+use log::{error, info, warn};
 
 use memflow::cglue;
 use memflow::mem::phys_mem::*;
@@ -16,36 +14,44 @@ use memflow::prelude::v1::*;
 
 use leechcore_sys::*;
 
+mod handle;
+pub use handle::{LcCreateError, LcErrorInfo, LcHandle};
+
+mod option;
+pub use option::{LcOption, LcOptionKind, LcOptionValue, LcVersion};
+
+mod fpga_args;
+
+mod mem_map;
+pub use mem_map::{MemMapFormat, MemMapPolicy};
+
+mod memory_info;
+pub use memory_info::LcMemoryInfo;
+
+mod printf;
+
+mod scatter;
+use scatter::{PageChunk, ScatterPool};
+
+mod statistics;
+pub use statistics::{LcCallStatistics, LcStatistics, RetryStatistics};
+
 const PAGE_SIZE: usize = 0x1000usize;
 
-// the absolute minimum BUF_ALIGN is 4.
-// using 8 bytes as BUF_ALIGN here simplifies things a lot
-// and makes our gap detection code work in cases where page boundaries would be crossed.
-const BUF_ALIGN: u64 = 8;
-const BUF_MIN_LEN: usize = 8;
-const BUF_LEN_ALIGN: usize = 8;
+// the batch size reported for devices that do not limit the size of read requests
+const DEFAULT_BATCH_SIZE: u32 = 128;
+
+// the number of pages dispatched to leechcore in a single scatter call by default
+const DEFAULT_MAX_BATCH_PAGES: usize = 0x2000;
+
+// the minimum number of contiguous pages that are read and written via LcRead / LcWrite by default
+const DEFAULT_BULK_MIN_PAGES: usize = 16;
 
 cglue_impl_group!(PciLeech, ConnectorInstance<'a>, {});
 
 fn build_lc_config(device: &str, remote: Option<&str>, with_mem_map: bool) -> LC_CONFIG {
-    // configure verbosity based on current level
-    let printf_verbosity = match log::max_level() {
-        LevelFilter::Off => 0,
-        LevelFilter::Error | LevelFilter::Warn => LC_CONFIG_PRINTF_ENABLED,
-        LevelFilter::Info => LC_CONFIG_PRINTF_ENABLED | LC_CONFIG_PRINTF_V,
-        LevelFilter::Debug => {
-            LC_CONFIG_PRINTF_ENABLED
-                | LC_CONFIG_PRINTF_V
-                | LC_CONFIG_PRINTF_ENABLED
-                | LC_CONFIG_PRINTF_VV
-        }
-        LevelFilter::Trace => {
-            LC_CONFIG_PRINTF_ENABLED
-                | LC_CONFIG_PRINTF_V
-                | LC_CONFIG_PRINTF_ENABLED
-                | LC_CONFIG_PRINTF_VVV
-        }
-    };
+    // configure verbosity based on the levels enabled for leechcore messages
+    let printf_verbosity = printf::verbosity();
 
     // TODO: refactor how the static strings are handled
     let cdevice = unsafe { &*(device.as_bytes() as *const [u8] as *const [c_char]) };
@@ -67,7 +73,7 @@ fn build_lc_config(device: &str, remote: Option<&str>, with_mem_map: bool) -> LC
         dwPrintfVerbosity: printf_verbosity,
         szDevice: adevice,
         szRemote: aremote,
-        pfn_printf_opt: None, // TODO: custom info() wrapper
+        pfn_printf_opt: printf::init(printf_verbosity),
         paMax: pa_max,
 
         // these are set by leechcore so we dont touch them
@@ -79,25 +85,49 @@ fn build_lc_config(device: &str, remote: Option<&str>, with_mem_map: bool) -> LC
     }
 }
 
-const fn calc_num_pages(start: u64, size: u64) -> u64 {
-    ((start & (PAGE_SIZE as u64 - 1)) + size + (PAGE_SIZE as u64 - 1)) >> 12
+/// Returns the number of pages the device handles best in a single request.
+fn ideal_batch_size(handle: &LcHandle) -> u32 {
+    // fpga devices limit the amount of data that can be received in a single request
+    handle
+        .get_option(LC_OPT_FPGA_MAX_SIZE_RX)
+        .map(|max_size_rx| (max_size_rx / PAGE_SIZE as u64).clamp(1, u32::MAX as u64) as u32)
+        .unwrap_or(DEFAULT_BATCH_SIZE)
+}
+
+/// Memory map state that is shared between all clones of a connector.
+struct MemMapState {
+    policy: MemMapPolicy,
+    /// The memory map provided by the user, e.g. via the `memmap` argument.
+    user: Option<Vec<PhysicalMemoryMapping>>,
+    /// The memory map provided by the os layer via `set_mem_map`.
+    os: Option<Vec<PhysicalMemoryMapping>>,
+    /// True if the os map was provided after the user map.
+    os_latest: bool,
+    /// The memory map in effect.
+    effective: Option<MemoryMap<(Address, umem)>>,
+    push: bool,
+    export: Option<PathBuf>,
 }
 
-#[allow(clippy::mutex_atomic)]
 #[derive(Clone)]
 pub struct PciLeech {
-    handle: Arc<Mutex<HANDLE>>,
+    handle: Arc<Mutex<LcHandle>>,
     conf: LC_CONFIG,
-    mem_map: Option<MemoryMap<(Address, umem)>>,
+    addr_max: u64,
+    ideal_batch_size: u32,
+    max_batch_pages: usize,
+    bulk_min_pages: Option<usize>,
+    retries: u32,
+    retry_delay: Duration,
+    scatter_pool: Arc<Mutex<ScatterPool>>,
+    mem_map: Arc<Mutex<MemMapState>>,
+    stats_baseline: Arc<Mutex<Option<LC_STATISTICS>>>,
+    retry_stats: Arc<Mutex<RetryStatistics>>,
 }
 
-unsafe impl Send for PciLeech {}
-
-// TODO: proper drop + free impl -> LcMemFree(pLcErrorInfo);
-#[allow(clippy::mutex_atomic)]
 impl PciLeech {
     pub fn new(device: &str, remote: Option<&str>, auto_clear: bool) -> Result<Self> {
-        Self::new_internal(device, remote, None, auto_clear)
+        Self::new_internal(device, remote, false, auto_clear)
     }
 
     pub fn with_mem_map_file<P: AsRef<Path>>(
@@ -106,69 +136,73 @@ impl PciLeech {
         path: P,
         auto_clear: bool,
     ) -> Result<Self> {
+        let mem_map = Self::open_mem_map_file(path)?;
+        let connector = Self::new_internal(device, remote, true, auto_clear)?;
+        connector.swap_mem_map(&mem_map)?;
+        Ok(connector)
+    }
+
+    fn open_mem_map_file<P: AsRef<Path>>(path: P) -> Result<Vec<PhysicalMemoryMapping>> {
         info!(
             "loading memory mappings from file: {}",
             path.as_ref().to_string_lossy()
         );
-        let mem_map = MemoryMap::open(path)?;
+        let mem_map = mem_map::open(path)?;
         info!("{:?}", mem_map);
-        Self::new_internal(device, remote, Some(mem_map), auto_clear)
+        Ok(mem_map)
     }
 
-    #[allow(clippy::mutex_atomic)]
-    fn new_internal(
+    /// Creates a new connector and uses the memory map reported by LeechCore itself.
+    ///
+    /// LeechCore obtains the memory map e.g. from the header of a crash dump or via auto-detection.
+    pub fn with_leechcore_mem_map(
         device: &str,
         remote: Option<&str>,
-        mem_map: Option<MemoryMap<(Address, umem)>>,
         auto_clear: bool,
     ) -> Result<Self> {
-        // open device
-        let mut conf = build_lc_config(device, remote, mem_map.is_some());
-        let p_lc_config_error_info = std::ptr::null_mut::<LC_CONFIG_ERRORINFO>();
-        let pp_lc_config_error_info =
-            &raw const p_lc_config_error_info as *mut PLC_CONFIG_ERRORINFO;
-        let handle = unsafe { LcCreateEx(&mut conf, pp_lc_config_error_info) };
-        if handle.is_null() {
-            error!("Unable to create leechcore context: {conf:?} ppErr: {pp_lc_config_error_info:?} pErr: {p_lc_config_error_info:?}");
-            // TODO: handle version error
-            // TODO: handle special case of fUserInputRequest
-            let err = if p_lc_config_error_info.is_null() {
-                None
-            } else {
-                // read the data at the error
-                Some(unsafe { p_lc_config_error_info.read() })
-            };
-
-            return Err(Error(ErrorOrigin::Connector, ErrorKind::Configuration)
-                .log_error(format!("unable to create leechcore context: {err:?}",)));
+        let connector = Self::new_internal(device, remote, false, auto_clear)?;
+        info!("loading memory mappings from leechcore");
+        let mem_map = connector.leechcore_mem_map()?;
+        if mem_map.is_empty() {
+            return Err(Error(ErrorOrigin::Connector, ErrorKind::NotFound)
+                .log_error("leechcore did not report any memory mappings"));
         }
+        info!("{:?}", mem_map);
 
-        // TODO: allow handling these errors properly
-        /*
-            typedef struct tdLC_CONFIG_ERRORINFO {
-            DWORD dwVersion;                        // must equal LC_CONFIG_ERRORINFO_VERSION
-            DWORD cbStruct;
-            DWORD _FutureUse[16];
-            BOOL fUserInputRequest;
-            DWORD cwszUserText;
-            WCHAR wszUserText[];
-        } LC_CONFIG_ERRORINFO, *PLC_CONFIG_ERRORINFO, **PPLC_CONFIG_ERRORINFO;
-        */
+        // the map always has to be sent back to leechcore,
+        // otherwise it would remap the already translated addresses a second time
+        mem_map::push_leechcore_mem_map(&connector.handle.lock(), &mem_map)?;
+        let mut state = connector.mem_map.lock();
+        state.user = Some(mem_map::mappings(&mem_map));
+        state.effective = Some(mem_map);
+        drop(state);
+        Ok(connector)
+    }
+
+    fn new_internal(
+        device: &str,
+        remote: Option<&str>,
+        with_mem_map: bool,
+        auto_clear: bool,
+    ) -> Result<Self> {
+        // open device
+        let mut conf = build_lc_config(device, remote, with_mem_map);
+        let handle = LcHandle::create(&mut conf).map_err(|err| {
+            Error::from(err.clone()).log_error(format!("unable to create leechcore context: {err}"))
+        })?;
 
         if auto_clear {
-            let (mut id, mut version_major, mut version_minor) = (0, 0, 0);
-            unsafe {
-                LcGetOption(handle, LC_OPT_FPGA_FPGA_ID, &mut id);
-                LcGetOption(handle, LC_OPT_FPGA_VERSION_MAJOR, &mut version_major);
-                LcGetOption(handle, LC_OPT_FPGA_VERSION_MINOR, &mut version_minor);
-            }
-            if version_major >= 4 && (version_major >= 5 || version_minor >= 7) {
+            let version = LcOption::FpgaVersion
+                .get(&handle)?
+                .as_version()
+                .unwrap_or_default();
+            if (version.major, version.minor) >= (4, 7) {
                 // enable auto-clear of status register [master abort].
                 info!("Trying to enable status register auto-clear");
                 let mut data = [0x10, 0x00, 0x10, 0x00];
                 if unsafe {
                     LcCommand(
-                        handle,
+                        handle.as_raw(),
                         LC_CMD_FPGA_CFGREGPCIE_MARKWR | 0x002,
                         data.len() as u32,
                         data.as_mut_ptr(),
@@ -188,338 +222,496 @@ impl PciLeech {
             }
         }
 
-        #[allow(clippy::arc_with_non_send_sync)]
+        // the config only contains the requested limit, the actual one is reported by the device
+        let addr_max = handle
+            .get_option(LC_OPT_CORE_ADDR_MAX)
+            .unwrap_or(conf.paMax);
+
+        let ideal_batch_size = ideal_batch_size(&handle);
+
         Ok(Self {
             handle: Arc::new(Mutex::new(handle)),
             conf,
-            mem_map,
+            addr_max,
+            ideal_batch_size,
+            max_batch_pages: DEFAULT_MAX_BATCH_PAGES,
+            bulk_min_pages: Some(DEFAULT_BULK_MIN_PAGES),
+            retries: 0,
+            retry_delay: Duration::default(),
+            scatter_pool: Arc::new(Mutex::new(ScatterPool::default())),
+            mem_map: Arc::new(Mutex::new(MemMapState {
+                policy: MemMapPolicy::default(),
+                user: None,
+                os: None,
+                os_latest: false,
+                effective: None,
+                push: true,
+                export: None,
+            })),
+            stats_baseline: Arc::new(Mutex::new(None)),
+            retry_stats: Arc::new(Mutex::new(RetryStatistics::default())),
         })
     }
-}
 
-struct ReadGap {
-    gap_buffer: *mut u8,
-    gap_buffer_len: usize,
-    out_buffer: *mut u8,
-    out_start: usize,
-    out_end: usize,
-}
+    /// Enables or disables sending the memory map in use to LeechCore.
+    ///
+    /// When enabled (the default) LeechCore's own range checks and statistics agree with the connector.
+    /// The setting applies to this connector and all of its clones.
+    pub fn set_mem_map_push(&self, enabled: bool) {
+        self.mem_map.lock().push = enabled;
+    }
 
-struct WriteGap {
-    gap_addr: PhysicalAddress,
-    gap_buffer: *mut u8,
-    gap_buffer_len: usize,
-    in_buffer: *const u8,
-    in_start: usize,
-    in_end: usize,
-}
+    /// Sets the maximum number of pages that are dispatched to LeechCore in a single scatter call.
+    ///
+    /// Larger requests are split into multiple calls.
+    pub fn set_max_batch_pages(&mut self, max_batch_pages: usize) -> Result<()> {
+        if max_batch_pages == 0 || max_batch_pages > scatter::MAX_BATCH_PAGES {
+            return Err(
+                Error(ErrorOrigin::Connector, ErrorKind::InvalidArgument).log_error(format!(
+                    "the maximum batch size has to be between 1 and {} pages",
+                    scatter::MAX_BATCH_PAGES
+                )),
+            );
+        }
+        self.max_batch_pages = max_batch_pages;
+        Ok(())
+    }
 
-impl PhysicalMemory for PciLeech {
-    fn phys_read_raw_iter<'a>(&mut self, mut data: PhysicalReadMemOps) -> Result<()> {
-        let mut vec = if let Some(mem_map) = &self.mem_map {
-            mem_map
-                .map_iter(data.inp, data.out_fail)
-                .map(|d| (d.0 .0.into(), d.1, d.2))
-                .collect::<Vec<_>>()
-        } else {
-            data.inp.map(|d| (d.0, d.1, d.2)).collect::<Vec<_>>()
-        };
+    /// Sets the minimum number of pages a page aligned request needs to span
+    /// to be dispatched as a whole via `LcRead` / `LcWrite` instead of one scatter entry per page.
+    ///
+    /// Blocks that fail are retried page by page so failures are still reported for every page.
+    /// `None` disables the bulk path.
+    pub fn set_bulk_min_pages(&mut self, bulk_min_pages: Option<usize>) {
+        self.bulk_min_pages = bulk_min_pages.map(|pages| pages.max(1));
+    }
 
-        // get total number of pages
-        let num_pages = vec.iter().fold(0u64, |acc, read| {
-            acc + calc_num_pages(read.0.to_umem(), read.2.len() as u64)
-        });
+    /// Sets how often pages that failed to be read or written are re-issued before the failure is reported.
+    ///
+    /// Only the pages that failed are re-issued, `delay` is waited before every retry.
+    pub fn set_retries(&mut self, retries: u32, delay: Duration) {
+        self.retries = retries;
+        self.retry_delay = delay;
+    }
 
-        // allocate scatter buffer
-        let mut mems = std::ptr::null_mut::<PMEM_SCATTER>();
-        let result = unsafe {
-            LcAllocScatter2(
-                (num_pages * PAGE_SIZE as u64) as u32,
-                std::ptr::null_mut(),
-                num_pages as u32,
-                &mut mems as *mut PPMEM_SCATTER,
-            )
-        };
-        if result != 1 {
-            return Err(Error(ErrorOrigin::Connector, ErrorKind::InvalidMemorySize)
-                .log_error("unable to allocate scatter buffer"));
+    fn record_retries(&self, retried: usize, recovered: usize, failed: usize) {
+        if retried > 0 || failed > 0 {
+            let mut stats = self.retry_stats.lock();
+            stats.retried_pages += retried as u64;
+            stats.recovered_pages += recovered as u64;
+            stats.failed_pages += failed as u64;
         }
+    }
 
-        // prepare mems
-        let mut gaps = Vec::new();
-        let mut i = 0usize;
-        for (addr, _, out) in vec.iter_mut() {
-            for (page_addr, out) in CSliceMut::from(out).page_chunks(addr.address(), PAGE_SIZE) {
-                let mem = unsafe { *mems.add(i) };
-
-                let addr_align = page_addr.to_umem() & (BUF_ALIGN - 1);
-                let len_align = out.len() & (BUF_LEN_ALIGN - 1);
-
-                if addr_align == 0 && len_align == 0 && out.len() >= BUF_MIN_LEN {
-                    // properly aligned read
-                    unsafe { (*mem).qwA = page_addr.to_umem() };
-                    unsafe { (*mem).__bindgen_anon_1.pb = out.as_mut_ptr() };
-                    unsafe { (*mem).cb = out.len() as u32 };
-                } else {
-                    // non-aligned or small read
-                    let page_addr_align = page_addr.to_umem() - addr_align;
-                    let mut buffer_len = out.len() + addr_align as usize;
-                    let buf_align = buffer_len & (BUF_LEN_ALIGN - 1);
-                    if buf_align > 0 {
-                        buffer_len += BUF_LEN_ALIGN - buf_align;
-                    }
-                    buffer_len = buffer_len.max(BUF_MIN_LEN);
-
-                    // note that this always holds true because addr alignment is equal to buf length alignment
-                    assert!(buffer_len >= out.len());
-
-                    // we never want to cross page boundaries, otherwise the read will just not work
-                    assert_eq!(
-                        page_addr.to_umem() - (page_addr.to_umem() & (PAGE_SIZE as umem - 1)),
-                        (page_addr_align + buffer_len as umem - 1)
-                            - ((page_addr_align + buffer_len as umem - 1)
-                                & (PAGE_SIZE as umem - 1))
-                    );
-
-                    let buffer = vec![0u8; buffer_len].into_boxed_slice();
-                    let buffer_ptr = Box::into_raw(buffer) as *mut u8;
-
-                    gaps.push(ReadGap {
-                        gap_buffer: buffer_ptr,
-                        gap_buffer_len: buffer_len,
-                        out_buffer: out.as_mut_ptr(),
-                        out_start: addr_align as usize,
-                        out_end: out.len() + addr_align as usize,
-                    });
-
-                    unsafe { (*mem).qwA = page_addr_align };
-                    unsafe { (*mem).__bindgen_anon_1.pb = buffer_ptr };
-                    unsafe { (*mem).cb = buffer_len as u32 };
-                }
+    /// Applies the FPGA tuning arguments (e.g. `fpga-delay-read`) to the device.
+    fn apply_fpga_args(&mut self, args: &Args) -> Result<()> {
+        let handle = self.handle.lock();
+        if fpga_args::apply(&handle, args)? {
+            // the maximum transfer size of the device might have changed
+            self.ideal_batch_size = ideal_batch_size(&handle);
+        }
+        Ok(())
+    }
 
-                i += 1;
+    /// Returns true if the request should be dispatched via the bulk path.
+    fn use_bulk(&self, addr: Address, len: usize) -> bool {
+        match self.bulk_min_pages {
+            Some(min_pages) => {
+                addr.to_umem() & (PAGE_SIZE as umem - 1) == 0
+                    && len & (PAGE_SIZE - 1) == 0
+                    && len / PAGE_SIZE >= min_pages
             }
+            None => false,
         }
+    }
 
-        // dispatch read
-        {
-            let handle = self.handle.lock();
-            unsafe {
-                LcReadScatter(*handle, num_pages as u32, mems);
+    /// Dispatches the requests of a read or write operation to LeechCore.
+    ///
+    /// Large page aligned requests are dispatched as a whole via `bulk_op`,
+    /// all other requests are split into page chunks and dispatched in bounded batches via `scatter_op`.
+    /// Chunks that fail are re-issued according to the retry policy before they are reported via `out_fail`.
+    fn dispatch<T, B, S>(
+        &self,
+        requests: Vec<(PhysicalAddress, Address, T)>,
+        mut out: Option<&mut OpaqueCallback<'_, CTup2<Address, T>>>,
+        mut out_fail: Option<&mut OpaqueCallback<'_, CTup2<Address, T>>>,
+        mut bulk_op: B,
+        mut scatter_op: S,
+    ) -> Result<()>
+    where
+        T: SplitAtIndex + Deref<Target = [u8]>,
+        B: FnMut(&LcHandle, Address, &mut T) -> bool,
+        S: FnMut(&mut ScatterPool, &LcHandle, &mut [PageChunk<T>], &mut Vec<bool>) -> Result<()>,
+    {
+        // blocks that fail in the bulk path are dispatched page by page below
+        // to find out which pages are inaccessible
+        let mut scattered = Vec::with_capacity(requests.len());
+        for (addr, meta_addr, buf) in requests {
+            if !self.use_bulk(addr.address(), buf.len()) {
+                scattered.push((addr, meta_addr, buf));
+                continue;
             }
-        }
 
-        // gather all 'bogus' reads we had to custom-allocate
-        if !gaps.is_empty() {
-            for gap in gaps.iter() {
-                let buffer: Box<[u8]> = unsafe {
-                    Box::from_raw(ptr::slice_from_raw_parts_mut(
-                        gap.gap_buffer,
-                        gap.gap_buffer_len,
-                    ))
-                };
-
-                let out_buffer = unsafe {
-                    slice::from_raw_parts_mut(gap.out_buffer, gap.out_end - gap.out_start)
-                };
-                out_buffer.copy_from_slice(&buffer[gap.out_start..gap.out_end]);
-
-                // drop buffer
+            let mut offset: umem = 0;
+            let mut rest = Some(buf);
+            while let Some((Some(mut block), tail)) =
+                rest.map(|buf| buf.split_at((self.max_batch_pages * PAGE_SIZE) as umem))
+            {
+                rest = tail;
+                let block_addr = addr.address() + offset;
+                let block_meta_addr = meta_addr + offset;
+                offset += block.len() as umem;
+
+                if bulk_op(&self.handle.lock(), block_addr, &mut block) {
+                    opt_call(out.as_deref_mut(), CTup2(block_meta_addr, block));
+                } else {
+                    scattered.push((block_addr.into(), block_meta_addr, block));
+                }
             }
         }
 
-        // free temporary buffers
-        unsafe {
-            LcMemFree(mems as *mut c_void);
-        };
+        // split all requests into page chunks which are dispatched in bounded batches
+        let mut chunks = scattered.into_iter().flat_map(|(addr, meta_addr, buf)| {
+            buf.page_chunks(addr.address(), PAGE_SIZE)
+                .map(move |(page_addr, buf)| PageChunk {
+                    addr: page_addr,
+                    meta_addr: meta_addr + (page_addr.to_umem() - addr.to_umem()),
+                    buf,
+                })
+        });
+
+        let mut batch = Vec::new();
+        let mut failed = Vec::new();
+        let mut results = Vec::new();
+        loop {
+            batch.extend(chunks.by_ref().take(self.max_batch_pages));
+            if batch.is_empty() {
+                break;
+            }
+
+            // failed chunks are re-issued until they succeed or the retries are exhausted
+            let mut attempt = 0;
+            let mut retried = 0;
+            let mut recovered = 0;
+            loop {
+                {
+                    // the handle is locked for the entire read-modify-write cycle of writes
+                    let handle = self.handle.lock();
+                    scatter_op(
+                        &mut self.scatter_pool.lock(),
+                        &handle,
+                        &mut batch,
+                        &mut results,
+                    )?;
+                }
+
+                // call out success for every chunk based on `f` in the scatter entries
+                for (chunk, &success) in batch.drain(..).zip(results.iter()) {
+                    if success {
+                        opt_call(out.as_deref_mut(), CTup2(chunk.meta_addr, chunk.buf));
+                    } else {
+                        failed.push(chunk);
+                    }
+                }
+                if attempt > 0 {
+                    recovered += results.len() - failed.len();
+                }
 
-        // call out sucess for everything
-        // TODO: implement proper callback based on `f` in scatter
-        for (_, meta_addr, out) in vec.into_iter() {
-            opt_call(data.out.as_deref_mut(), CTup2(meta_addr, out));
+                if failed.is_empty() || attempt == self.retries {
+                    break;
+                }
+                attempt += 1;
+                retried += failed.len();
+                if !self.retry_delay.is_zero() {
+                    std::thread::sleep(self.retry_delay);
+                }
+                std::mem::swap(&mut batch, &mut failed);
+            }
+
+            // chunks that still fail are only reported once all retries are exhausted
+            self.record_retries(retried, recovered, failed.len());
+            for chunk in failed.drain(..) {
+                opt_call(out_fail.as_deref_mut(), CTup2(chunk.meta_addr, chunk.buf));
+            }
         }
 
         Ok(())
     }
 
-    fn phys_write_raw_iter<'a>(&mut self, mut data: PhysicalWriteMemOps) -> Result<()> {
-        let vec = if let Some(mem_map) = &self.mem_map {
-            mem_map
-                .map_iter(data.inp, data.out_fail)
-                .map(|d| (d.0 .0.into(), d.1, d.2))
-                .collect::<Vec<_>>()
-        } else {
-            data.inp.map(|d| (d.0, d.1, d.2)).collect::<Vec<_>>()
-        };
+    /// Returns the policy used to combine the user memory map with the one of the os layer.
+    pub fn mem_map_policy(&self) -> MemMapPolicy {
+        self.mem_map.lock().policy
+    }
 
-        // get total number of pages
-        let num_pages = vec.iter().fold(0u64, |acc, read| {
-            acc + calc_num_pages(read.0.to_umem(), read.2.len() as u64)
-        });
+    /// Changes the policy used to combine the user memory map with the one of the os layer.
+    ///
+    /// The memory map in effect is updated for this connector and all of its clones.
+    pub fn set_mem_map_policy(&self, policy: MemMapPolicy) {
+        let mut state = self.mem_map.lock();
+        state.policy = policy;
+        self.update_mem_map(&mut state);
+    }
 
-        // allocate scatter buffer
-        let mut mems = std::ptr::null_mut::<PMEM_SCATTER>();
-        let result = unsafe {
-            LcAllocScatter2(
-                (num_pages * PAGE_SIZE as u64) as u32,
-                std::ptr::null_mut(),
-                num_pages as u32,
-                &mut mems as *mut PPMEM_SCATTER,
-            )
+    /// Replaces the user memory map at runtime.
+    ///
+    /// The memory map in effect is updated according to the memory map policy
+    /// for this connector and all of its clones.
+    pub fn swap_mem_map(&self, mappings: &[PhysicalMemoryMapping]) -> Result<()> {
+        let mappings = self.validate_mem_map(mappings)?;
+        let mut state = self.mem_map.lock();
+        state.user = Some(mappings);
+        state.os_latest = false;
+        self.update_mem_map(&mut state);
+        Ok(())
+    }
+
+    fn validate_mem_map(
+        &self,
+        mappings: &[PhysicalMemoryMapping],
+    ) -> Result<Vec<PhysicalMemoryMapping>> {
+        let addr_max = self.handle.lock().get_option(LC_OPT_CORE_ADDR_MAX);
+        mem_map::validate(mappings, addr_max)
+    }
+
+    fn update_mem_map(&self, state: &mut MemMapState) {
+        let mappings =
+            state
+                .policy
+                .combine(state.user.as_deref(), state.os.as_deref(), state.os_latest);
+        let mem_map = match mappings {
+            Some(mappings) => MemoryMap::from_vec(mappings),
+            None => return,
         };
-        if result != 1 {
-            return Err(Error(ErrorOrigin::Connector, ErrorKind::InvalidMemorySize)
-                .log_error("unable to allocate scatter buffer"));
+
+        if state.push {
+            if let Err(err) = mem_map::push_leechcore_mem_map(&self.handle.lock(), &mem_map) {
+                warn!("unable to send memory map to leechcore: {}", err);
+            }
         }
+        state.effective = Some(mem_map);
+        Self::write_mem_map_export(state);
+    }
 
-        // prepare mems
-        let mut gaps = Vec::new();
-        let mut i = 0usize;
-        for write in vec.iter() {
-            for (page_addr, out) in write.2.page_chunks(write.0.into(), PAGE_SIZE) {
-                let mem = unsafe { *mems.add(i) };
-
-                let addr_align = page_addr.to_umem() & (BUF_ALIGN - 1);
-                let len_align = out.len() & (BUF_LEN_ALIGN - 1);
-
-                if addr_align == 0 && len_align == 0 && out.len() >= BUF_MIN_LEN {
-                    // properly aligned write
-                    unsafe { (*mem).qwA = page_addr.to_umem() };
-                    unsafe { (*mem).__bindgen_anon_1.pb = out.as_ptr() as *mut u8 };
-                    unsafe { (*mem).cb = out.len() as u32 };
-                } else {
-                    // non-aligned or small write
-                    let page_addr_align = page_addr.to_umem() - addr_align;
-                    let mut buffer_len = out.len() + addr_align as usize;
-                    let buf_align = buffer_len & (BUF_LEN_ALIGN - 1);
-                    if buf_align > 0 {
-                        buffer_len += BUF_LEN_ALIGN - buf_align;
-                    }
-                    buffer_len = buffer_len.max(BUF_MIN_LEN);
-
-                    // note that this always holds true because addr alignment is equal to buf length alignment
-                    assert!(buffer_len >= out.len());
-
-                    // we never want to cross page boundaries, otherwise the write will just not work
-                    assert_eq!(
-                        page_addr.to_umem() - (page_addr.to_umem() & (PAGE_SIZE as umem - 1)),
-                        (page_addr_align + buffer_len as umem - 1)
-                            - ((page_addr_align + buffer_len as umem - 1)
-                                & (PAGE_SIZE as umem - 1))
-                    );
-
-                    // prepare gap buffer for writing
-                    let buffer = vec![0u8; buffer_len].into_boxed_slice();
-                    let buffer_ptr = Box::into_raw(buffer) as *mut u8;
-
-                    // send over to our gaps list
-                    gaps.push(WriteGap {
-                        gap_addr: page_addr_align.into(),
-                        gap_buffer: buffer_ptr,
-                        gap_buffer_len: buffer_len,
-                        in_buffer: out.as_ptr(),
-                        in_start: addr_align as usize,
-                        in_end: out.len() + addr_align as usize,
-                    });
-
-                    // store pointers into pcileech struct for writing (after we dispatched a read)
-                    unsafe { (*mem).qwA = page_addr_align };
-                    unsafe { (*mem).__bindgen_anon_1.pb = buffer_ptr };
-                    unsafe { (*mem).cb = buffer_len as u32 };
-                }
+    /// Writes the memory map in effect to the given TOML file now and whenever it changes.
+    ///
+    /// This allows later sessions to start with the memory map found by the os layer (e.g. win32).
+    /// The setting applies to this connector and all of its clones.
+    pub fn export_mem_map<P: AsRef<Path>>(&self, path: P) -> Result<()> {
+        let mut state = self.mem_map.lock();
+        state.export = Some(path.as_ref().to_path_buf());
+        if let Some(mem_map) = &state.effective {
+            mem_map::save(path, mem_map, MemMapFormat::Toml)?;
+        }
+        Ok(())
+    }
 
-                i += 1;
+    fn write_mem_map_export(state: &MemMapState) {
+        if let (Some(path), Some(mem_map)) = (&state.export, &state.effective) {
+            info!(
+                "exporting memory mappings to file: {}",
+                path.to_string_lossy()
+            );
+            if let Err(err) = mem_map::save(path, mem_map, MemMapFormat::Toml) {
+                warn!("unable to export memory map: {}", err);
             }
         }
+    }
 
-        // dispatch necessary reads to fill the gaps
-        if !gaps.is_empty() {
-            let mut vec: Vec<CTup2<PhysicalAddress, &mut [u8]>> = gaps
-                .iter()
-                .map(|g| {
-                    CTup2(g.gap_addr, unsafe {
-                        slice::from_raw_parts_mut(g.gap_buffer, g.gap_buffer_len)
-                    })
-                })
-                .collect::<Vec<_>>();
-
-            let mut iter = vec
-                .iter_mut()
-                .map(|CTup2(a, d)| (*a, CSliceRef::from(d.as_bytes())));
+    /// Retrieves the current value of the given LeechCore option.
+    pub fn get_option(&self, option: LcOption) -> Result<LcOptionValue> {
+        let handle = self.handle.lock();
+        option.get(&handle)
+    }
 
-            MemOps::with(&mut iter, None, None, |data| self.phys_write_raw_iter(data))?;
+    /// Changes the value of the given LeechCore option.
+    ///
+    /// The type of `value` has to match `option.kind()`.
+    pub fn set_option(&self, option: LcOption, value: LcOptionValue) -> Result<()> {
+        let handle = self.handle.lock();
+        option.set(&handle, value)
+    }
 
-            for (gap, read) in gaps.iter().zip(vec) {
-                let in_buffer =
-                    unsafe { slice::from_raw_parts(gap.in_buffer, gap.in_end - gap.in_start) };
-                read.1[gap.in_start..gap.in_end].copy_from_slice(in_buffer);
-            }
-        }
+    /// Writes the memory map currently in use to the given file.
+    pub fn save_mem_map<P: AsRef<Path>>(&self, path: P, format: MemMapFormat) -> Result<()> {
+        let state = self.mem_map.lock();
+        let mem_map = state.effective.as_ref().ok_or_else(|| {
+            Error(ErrorOrigin::Connector, ErrorKind::NotFound)
+                .log_error("no memory map is in use that could be saved")
+        })?;
+        mem_map::save(path, mem_map, format)
+    }
 
-        // TODO:
-        // opt_call(out.as_deref_mut(), CTup2(meta_addr, data));
+    /// Discovers the memory map of the target by probing all pages up to `LC_OPT_CORE_ADDR_MAX`.
+    ///
+    /// FPGA devices are probed via `LC_CMD_FPGA_PROBE`, all other devices by reading the pages.
+    /// `progress` is called after each chunk with the number of bytes probed so far and the total number of bytes.
+    /// The returned memory map is not put into use, see `swap_mem_map`.
+    pub fn probe_mem_map<F: FnMut(u64, u64)>(
+        &self,
+        mut progress: F,
+    ) -> Result<MemoryMap<(Address, umem)>> {
+        let mappings = mem_map::probe(&self.handle, &mut progress)?;
+        Ok(MemoryMap::from_vec(mappings))
+    }
 
-        // dispatch write
-        {
-            let handle = self.handle.lock();
-            unsafe {
-                LcWriteScatter(*handle, num_pages as u32, mems);
-            }
+    /// Probes the memory map or loads it from the given cache file if it exists.
+    ///
+    /// Freshly probed memory maps are written to the cache file.
+    fn probe_mem_map_cached(&self, cache: Option<&str>) -> Result<Vec<PhysicalMemoryMapping>> {
+        if let Some(cache) = cache.filter(|cache| Path::new(cache).exists()) {
+            info!("using cached memory map probe results from file: {}", cache);
+            return mem_map::open(cache);
         }
 
-        if !gaps.is_empty() {
-            for gap in gaps.iter() {
-                let _ = unsafe { Box::from_raw(gap.gap_buffer) };
-                // drop buffer
+        info!("probing memory map of the target");
+        let mut reported = 0;
+        let mem_map = self.probe_mem_map(|probed, total| {
+            let percent = probed * 100 / total.max(1);
+            if percent >= reported + 10 || probed == total {
+                info!("probed {:#x} of {:#x} bytes ({}%)", probed, total, percent);
+                reported = percent;
             }
+        })?;
+        info!("{:?}", mem_map);
+
+        if let Some(cache) = cache {
+            mem_map::save(cache, &mem_map, MemMapFormat::Toml)?;
         }
+        Ok(mem_map::mappings(&mem_map))
+    }
 
-        // free temporary buffers
-        unsafe {
-            LcMemFree(mems as *mut c_void);
-        };
+    /// Retrieves the memory map LeechCore is currently using.
+    pub fn leechcore_mem_map(&self) -> Result<MemoryMap<(Address, umem)>> {
+        mem_map::leechcore_mem_map(&self.handle.lock())
+    }
 
-        // call out sucess for everything
-        // TODO: implement proper callback based on `f` in scatter
-        for (_, meta_addr, out) in vec.into_iter() {
-            opt_call(data.out.as_deref_mut(), CTup2(meta_addr, out));
-        }
+    /// Retrieves the information LeechCore knows about the target system.
+    ///
+    /// Returns `None` if LeechCore does not have any information, e.g. for live devices.
+    /// The values can be used as hints for the os layer (e.g. the dtb and kernel base for win32).
+    pub fn memory_info(&self) -> Result<Option<LcMemoryInfo>> {
+        LcMemoryInfo::query(&self.handle.lock())
+    }
+
+    /// Retrieves a snapshot of the LeechCore call statistics.
+    ///
+    /// The statistics are relative to the last call to `reset_statistics`.
+    pub fn statistics(&self) -> Result<LcStatistics> {
+        let raw = statistics::raw_statistics(&self.handle.lock())?;
+        let baseline = self.stats_baseline.lock();
+        Ok(LcStatistics::from_raw(&raw, baseline.as_ref()))
+    }
 
+    /// Retrieves a snapshot of the retry counters.
+    ///
+    /// The counters are shared between all clones and relative to the last call to `reset_statistics`.
+    pub fn retry_statistics(&self) -> RetryStatistics {
+        *self.retry_stats.lock()
+    }
+
+    /// Records the current LeechCore call statistics as the baseline for `statistics`
+    /// and resets the retry counters.
+    ///
+    /// The baseline applies to this connector and all of its clones,
+    /// the statistics kept by LeechCore itself are not reset.
+    pub fn reset_statistics(&self) -> Result<()> {
+        *self.retry_stats.lock() = RetryStatistics::default();
+        let raw = statistics::raw_statistics(&self.handle.lock())?;
+        *self.stats_baseline.lock() = Some(raw);
         Ok(())
     }
+}
+
+impl PhysicalMemory for PciLeech {
+    fn phys_read_raw_iter<'a>(&mut self, mut data: PhysicalReadMemOps) -> Result<()> {
+        let vec = if let Some(mem_map) = &self.mem_map.lock().effective {
+            mem_map
+                .map_iter(data.inp, data.out_fail.as_deref_mut())
+                .map(|d| (d.0 .0.into(), d.1, d.2))
+                .collect::<Vec<_>>()
+        } else {
+            data.inp.map(|d| (d.0, d.1, d.2)).collect::<Vec<_>>()
+        };
+
+        self.dispatch(
+            vec,
+            data.out.as_deref_mut(),
+            data.out_fail.as_deref_mut(),
+            |handle, addr, block| scatter::read_contiguous(handle, addr, block),
+            |pool, handle, batch, results| pool.read(handle, batch, results),
+        )
+    }
+
+    fn phys_write_raw_iter<'a>(&mut self, mut data: PhysicalWriteMemOps) -> Result<()> {
+        let vec = if let Some(mem_map) = &self.mem_map.lock().effective {
+            mem_map
+                .map_iter(data.inp, data.out_fail.as_deref_mut())
+                .map(|d| (d.0 .0.into(), d.1, d.2))
+                .collect::<Vec<_>>()
+        } else {
+            data.inp.map(|d| (d.0, d.1, d.2)).collect::<Vec<_>>()
+        };
+
+        self.dispatch(
+            vec,
+            data.out.as_deref_mut(),
+            data.out_fail.as_deref_mut(),
+            |handle, addr, block| scatter::write_contiguous(handle, addr, block),
+            |pool, handle, batch, results| pool.write(handle, batch, results),
+        )
+    }
 
     fn metadata(&self) -> PhysicalMemoryMetadata {
-        let (max_address, real_size) = if let Some(mem_map) = &self.mem_map {
+        let (max_address, real_size) = if let Some(mem_map) = &self.mem_map.lock().effective {
             (mem_map.max_address(), mem_map.real_size())
         } else {
+            // LC_OPT_CORE_ADDR_MAX is the first address that is not accessible anymore
             (
-                (self.conf.paMax as usize - 1_usize).into(),
-                self.conf.paMax as umem,
+                self.addr_max.saturating_sub(1).into(),
+                self.addr_max as umem,
             )
         };
         PhysicalMemoryMetadata {
             max_address,
             real_size,
-            readonly: self.conf.fVolatile == 0,
-            ideal_batch_size: 128,
+            readonly: self.conf.fWritable == 0,
+            ideal_batch_size: self.ideal_batch_size,
         }
     }
 
-    // Sets the memory map only in cases where no previous memory map was being set by the end-user.
+    // Combines the memory map of the os layer with the one of the end-user according to the memory map policy.
     fn set_mem_map(&mut self, mem_map: &[PhysicalMemoryMapping]) {
-        if self.mem_map.is_none() {
-            self.mem_map = Some(MemoryMap::<(Address, umem)>::from_vec(mem_map.to_vec()));
+        match self.validate_mem_map(mem_map) {
+            Ok(mappings) => {
+                let mut state = self.mem_map.lock();
+                state.os = Some(mappings);
+                state.os_latest = true;
+                self.update_mem_map(&mut state);
+            }
+            Err(err) => warn!("ignoring memory map provided by the os layer: {}", err),
         }
     }
 }
 
 fn validator() -> ArgsValidator {
-    ArgsValidator::new()
+    let validator = ArgsValidator::new()
         .arg(ArgDescriptor::new("default").description("the target device to be used by LeechCore"))
         .arg(ArgDescriptor::new("device").description("the target device to be used by LeechCore"))
         .arg(ArgDescriptor::new("remote").description("the remote target to be used by LeechCore"))
-        .arg(ArgDescriptor::new("memmap").description("the memory map file of the target machine"))
-        .arg(ArgDescriptor::new("auto-clear").description("tries to enable the status register auto-clear function (only available for bitstreams 4.7 and upwards)"))
+        .arg(ArgDescriptor::new("memmap").description("the memory map file of the target machine, `leechcore` to use the memory map reported by LeechCore or `probe` to discover the memory map by probing the target"))
+        .arg(ArgDescriptor::new("probe-cache").description("file the probed memory map is cached in, it is loaded instead of probing if it exists"))
+        .arg(ArgDescriptor::new("memmap-policy").description("how the memory map of the user is combined with the one of the os layer: user-only (default), os-only, intersect, union or replace"))
+        .arg(ArgDescriptor::new("memmap-out").description("writes the memory map in effect to the given TOML file whenever it changes"))
+        .arg(ArgDescriptor::new("no-memmap-push").description("do not send the memory map in use to LeechCore"))
+        .arg(ArgDescriptor::new("max-batch-pages").description("the maximum number of pages dispatched to LeechCore in a single scatter call (default: 8192)"))
+        .arg(ArgDescriptor::new("bulk-min-pages").description("the minimum number of contiguous pages that are read and written via LcRead / LcWrite, 0 disables the bulk path (default: 16)"))
+        .arg(ArgDescriptor::new("retries").description("how often pages that failed to be read or written are re-issued before the failure is reported (default: 0)"))
+        .arg(ArgDescriptor::new("retry-delay").description("the delay in milliseconds before failed pages are re-issued (default: 0)"))
+        .arg(ArgDescriptor::new("auto-clear").description("tries to enable the status register auto-clear function (only available for bitstreams 4.7 and upwards)"));
+
+    fpga_args::FPGA_ARGS
+        .iter()
+        .fold(validator, |validator, arg| validator.arg(arg.descriptor()))
 }
 
 /// Creates a new PciLeech Connector instance.
@@ -540,11 +732,72 @@ pub fn create_connector(args: &ConnectorArgs) -> Result<PciLeech> {
                 })?;
             let remote = args.get("remote");
             let auto_clear = args.get("auto-clear").is_some();
-            if let Some(memmap) = args.get("memmap") {
-                PciLeech::with_mem_map_file(device, remote, memmap, auto_clear)
+
+            let memmap = args.get("memmap");
+            let mem_map = match memmap {
+                Some("leechcore") | Some("probe") | None => None,
+                Some(path) => Some(PciLeech::open_mem_map_file(path)?),
+            };
+
+            let memmap_policy = args
+                .get("memmap-policy")
+                .map(str::parse::<MemMapPolicy>)
+                .transpose()?
+                .unwrap_or_default();
+
+            let mut connector = if memmap == Some("leechcore") {
+                PciLeech::with_leechcore_mem_map(device, remote, auto_clear)?
             } else {
-                PciLeech::new(device, remote, auto_clear)
+                PciLeech::new_internal(device, remote, mem_map.is_some(), auto_clear)?
+            };
+            connector.apply_fpga_args(args)?;
+            if let Some(max_batch_pages) = args.get("max-batch-pages") {
+                let max_batch_pages = max_batch_pages.parse::<usize>().map_err(|err| {
+                    Error(ErrorOrigin::Connector, ErrorKind::ArgValidation)
+                        .log_error(format!("invalid 'max-batch-pages' argument: {err}"))
+                })?;
+                connector.set_max_batch_pages(max_batch_pages)?;
+            }
+            if let Some(bulk_min_pages) = args.get("bulk-min-pages") {
+                let bulk_min_pages = bulk_min_pages.parse::<usize>().map_err(|err| {
+                    Error(ErrorOrigin::Connector, ErrorKind::ArgValidation)
+                        .log_error(format!("invalid 'bulk-min-pages' argument: {err}"))
+                })?;
+                connector.set_bulk_min_pages(Some(bulk_min_pages).filter(|&pages| pages > 0));
+            }
+            if args.get("retries").is_some() || args.get("retry-delay").is_some() {
+                let retries = args
+                    .get("retries")
+                    .map(str::parse::<u32>)
+                    .transpose()
+                    .map_err(|err| {
+                        Error(ErrorOrigin::Connector, ErrorKind::ArgValidation)
+                            .log_error(format!("invalid 'retries' argument: {err}"))
+                    })?
+                    .unwrap_or_default();
+                let retry_delay = args
+                    .get("retry-delay")
+                    .map(str::parse::<u64>)
+                    .transpose()
+                    .map_err(|err| {
+                        Error(ErrorOrigin::Connector, ErrorKind::ArgValidation)
+                            .log_error(format!("invalid 'retry-delay' argument: {err}"))
+                    })?
+                    .unwrap_or_default();
+                connector.set_retries(retries, Duration::from_millis(retry_delay));
+            }
+            connector.set_mem_map_push(args.get("no-memmap-push").is_none());
+            connector.set_mem_map_policy(memmap_policy);
+            if let Some(path) = args.get("memmap-out") {
+                connector.export_mem_map(path)?;
+            }
+            if let Some(mem_map) = mem_map {
+                connector.swap_mem_map(&mem_map)?;
+            } else if memmap == Some("probe") {
+                let mem_map = connector.probe_mem_map_cached(args.get("probe-cache"))?;
+                connector.swap_mem_map(&mem_map)?;
             }
+            Ok(connector)
         }
         Err(err) => {
             error!(
@@ -0,0 +1,326 @@
+use std::fmt::Write;
+use std::mem::size_of;
+use std::path::Path;
+use std::ptr;
+use std::slice;
+
+use memflow::prelude::v1::*;
+use serde::Deserialize;
+
+use leechcore_sys::*;
+
+use crate::handle::LcHandle;
+
+mod linux;
+mod policy;
+mod probe;
+mod registry;
+mod text;
+mod validate;
+
+pub use policy::MemMapPolicy;
+pub(crate) use probe::probe;
+pub(crate) use validate::validate;
+
+/// The file formats a memory map can be written in.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MemMapFormat {
+    /// The memflow TOML format.
+    Toml,
+    /// The plain text format used by pcileech and MemProcFS.
+    LeechCore,
+}
+
+/// Loads a memory map from the given file.
+///
+/// The format of the file is detected automatically.
+/// Supported are memflow TOML files, the LeechCore text format used by pcileech and MemProcFS,
+/// Windows registry exports (`.reg`), the contents of `/proc/iomem`, `BIOS-e820` kernel log
+/// excerpts and dumps of `/sys/firmware/memmap`.
+/// A copy of the `/sys/firmware/memmap` directory can be passed as path as well.
+///
+/// The mappings are returned in the order they appear in the file and have to be checked via [`validate`].
+pub(crate) fn open<P: AsRef<Path>>(path: P) -> Result<Vec<PhysicalMemoryMapping>> {
+    if path.as_ref().is_dir() {
+        return linux::open_sysfs_dir(path);
+    }
+
+    let data = std::fs::read(path.as_ref()).map_err(|err| {
+        Error(ErrorOrigin::Connector, ErrorKind::UnableToReadFile).log_error(format!(
+            "unable to read memory map file {}: {}",
+            path.as_ref().to_string_lossy(),
+            err
+        ))
+    })?;
+
+    let text = decode_text(&data);
+    if registry::is_registry_export(&text) {
+        registry::parse(&text)
+    } else if linux::is_e820(&text) {
+        linux::parse_e820(&text)
+    } else if linux::is_sysfs_dump(&text) {
+        linux::parse_sysfs_dump(&text)
+    } else if linux::is_iomem(&text) {
+        linux::parse_iomem(&text)
+    } else if text::is_text_mem_map(&text) {
+        text::parse(&text)
+    } else {
+        parse_toml(&text)
+    }
+}
+
+#[derive(Deserialize)]
+struct TomlMemMap {
+    #[serde(default)]
+    range: Vec<TomlRange>,
+}
+
+#[derive(Deserialize)]
+struct TomlRange {
+    base: u64,
+    length: u64,
+    real_base: Option<u64>,
+}
+
+/// Parses a memflow TOML memory map.
+fn parse_toml(text: &str) -> Result<Vec<PhysicalMemoryMapping>> {
+    let file: TomlMemMap = toml::from_str(text).map_err(|err| {
+        Error(ErrorOrigin::Connector, ErrorKind::Configuration)
+            .log_error(format!("unable to parse memory map: {err}"))
+    })?;
+    Ok(file
+        .range
+        .iter()
+        .map(|range| {
+            mapping(
+                range.base,
+                range.length,
+                range.real_base.unwrap_or(range.base),
+            )
+        })
+        .collect())
+}
+
+/// Returns the mappings of the memory map sorted by their base address.
+pub(crate) fn mappings(mem_map: &MemoryMap<(Address, umem)>) -> Vec<PhysicalMemoryMapping> {
+    sorted_ranges(mem_map)
+        .into_iter()
+        .map(|(base, size, real_base)| mapping(base, size, real_base))
+        .collect()
+}
+
+fn mapping(base: u64, size: u64, real_base: u64) -> PhysicalMemoryMapping {
+    PhysicalMemoryMapping {
+        base: base.into(),
+        size: size as umem,
+        real_base: real_base.into(),
+    }
+}
+
+/// Writes the memory map to the given file in the requested format.
+pub(crate) fn save<P: AsRef<Path>>(
+    path: P,
+    mem_map: &MemoryMap<(Address, umem)>,
+    format: MemMapFormat,
+) -> Result<()> {
+    let text = match format {
+        MemMapFormat::Toml => format_toml(mem_map),
+        MemMapFormat::LeechCore => text::format(mem_map),
+    };
+    std::fs::write(path.as_ref(), text).map_err(|err| {
+        Error(ErrorOrigin::Connector, ErrorKind::UnableToWriteFile).log_error(format!(
+            "unable to write memory map file {}: {}",
+            path.as_ref().to_string_lossy(),
+            err
+        ))
+    })
+}
+
+/// Writes the memory map in the memflow TOML format.
+fn format_toml(mem_map: &MemoryMap<(Address, umem)>) -> String {
+    let mut text = String::new();
+    for (base, size, real_base) in sorted_ranges(mem_map) {
+        writeln!(text, "[[range]]\nbase={base:#x}\nlength={size:#x}").ok();
+        if real_base != base {
+            writeln!(text, "real_base={real_base:#x}").ok();
+        }
+        text.push('\n');
+    }
+    text
+}
+
+/// Returns the `(base, size, real_base)` triple of all mappings sorted by their base address.
+fn sorted_ranges(mem_map: &MemoryMap<(Address, umem)>) -> Vec<(u64, u64, u64)> {
+    let mut ranges = mem_map
+        .iter()
+        .map(|mapping| {
+            let (real_base, size) = *mapping.output();
+            (mapping.base().to_umem(), size, real_base.to_umem())
+        })
+        .collect::<Vec<_>>();
+    ranges.sort_unstable();
+    ranges
+}
+
+fn parse_hex(value: &str) -> Option<u64> {
+    let value = value.trim();
+    let value = value
+        .strip_prefix("0x")
+        .or_else(|| value.strip_prefix("0X"))
+        .unwrap_or(value);
+    u64::from_str_radix(value, 16).ok()
+}
+
+/// Decodes the contents of a memory map file into a string.
+fn decode_text(data: &[u8]) -> String {
+    // `reg export` writes UTF-16LE files with a byte order mark
+    if let Some(data) = data.strip_prefix(&[0xff, 0xfe]) {
+        let wide = data
+            .chunks_exact(2)
+            .map(|c| u16::from_le_bytes([c[0], c[1]]))
+            .collect::<Vec<_>>();
+        String::from_utf16_lossy(&wide)
+    } else {
+        String::from_utf8_lossy(data).into_owned()
+    }
+}
+
+/// Retrieves the memory map LeechCore is currently using via `LC_CMD_MEMMAP_GET_STRUCT`.
+pub(crate) fn leechcore_mem_map(handle: &LcHandle) -> Result<MemoryMap<(Address, umem)>> {
+    let data = handle
+        .command(LC_CMD_MEMMAP_GET_STRUCT, &[])
+        .ok_or_else(|| {
+            Error(ErrorOrigin::Connector, ErrorKind::NotSupported)
+                .log_error("unable to retrieve memory map from leechcore")
+        })?;
+
+    let mut mem_map = MemoryMap::new();
+    for chunk in data.chunks_exact(size_of::<LC_MEMMAP_ENTRY>()) {
+        let entry = unsafe { ptr::read_unaligned(chunk.as_ptr() as *const LC_MEMMAP_ENTRY) };
+        if entry.cb > 0 {
+            // paRemap contains the address of the range on the device itself
+            mem_map.push_remap(entry.pa.into(), entry.cb as umem, entry.paRemap.into());
+        }
+    }
+    Ok(mem_map)
+}
+
+/// Sends the memory map to LeechCore via `LC_CMD_MEMMAP_SET_STRUCT`.
+///
+/// The connector translates all addresses before they are handed over to LeechCore.
+/// The ranges are therefore set up in the address space of the device (`real_base`)
+/// without any additional remapping on the LeechCore side.
+pub(crate) fn push_leechcore_mem_map(
+    handle: &LcHandle,
+    mem_map: &MemoryMap<(Address, umem)>,
+) -> Result<()> {
+    let mut entries = mem_map
+        .iter()
+        .map(|mapping| {
+            let (real_base, size) = *mapping.output();
+            LC_MEMMAP_ENTRY {
+                pa: real_base.to_umem(),
+                cb: size,
+                paRemap: real_base.to_umem(),
+            }
+        })
+        .collect::<Vec<_>>();
+    entries.sort_by_key(|entry| entry.pa);
+
+    let data = unsafe {
+        slice::from_raw_parts(
+            entries.as_ptr() as *const u8,
+            entries.len() * size_of::<LC_MEMMAP_ENTRY>(),
+        )
+    };
+    handle
+        .command(LC_CMD_MEMMAP_SET_STRUCT, data)
+        .map(|_| ())
+        .ok_or_else(|| {
+            Error(ErrorOrigin::Connector, ErrorKind::NotSupported)
+                .log_error("unable to send memory map to leechcore")
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Writes the text to a temporary file and loads it via [`open`].
+    fn open_text(name: &str, text: &str) -> Vec<(u64, u64, u64)> {
+        let path = std::env::temp_dir().join(format!("memflow-pcileech-detect-{name}"));
+        std::fs::write(&path, text).unwrap();
+        open(&path)
+            .unwrap()
+            .iter()
+            .map(|m| (m.base.to_umem(), m.size, m.real_base.to_umem()))
+            .collect()
+    }
+
+    #[test]
+    fn detect_toml_with_comments() {
+        let text = "\
+# memory map of the target
+[[range]]
+base = 0x1000
+length = 0x9e000
+
+# remapped above 4 GiB
+[[range]]
+base = 0x100000
+length = 0x7ff00000
+real_base = 0x100000000
+";
+        assert_eq!(
+            open_text("toml", text),
+            [
+                (0x1000, 0x9e000, 0x1000),
+                (0x100000, 0x7ff00000, 0x1_0000_0000)
+            ]
+        );
+    }
+
+    #[test]
+    fn detect_text_with_comments() {
+        let text = "\
+# memory map of the target
+0000      1000 -    9efff
+0001    100000 - 7fffffff -> 100000000
+";
+        assert_eq!(
+            open_text("text", text),
+            [
+                (0x1000, 0x9e000, 0x1000),
+                (0x100000, 0x7ff00000, 0x1_0000_0000)
+            ]
+        );
+    }
+
+    #[test]
+    fn detect_kernel_log() {
+        // the kernel log is checked before /proc/iomem and the text format
+        let text = "\
+[    0.000000] BIOS-provided physical RAM map:
+[    0.000000] BIOS-e820: [mem 0x0000000000000000-0x000000000009fbff] usable
+[    0.000000] BIOS-e820: [mem 0x0000000000100000-0x000000007ffdffff] usable
+";
+        assert_eq!(
+            open_text("e820", text),
+            [(0x0, 0x9fc00, 0x0), (0x100000, 0x7fee0000, 0x100000)]
+        );
+    }
+
+    #[test]
+    fn detect_iomem() {
+        let text = "\
+00000000-00000fff : Reserved
+00001000-0009fbff : System RAM
+00100000-7ffdffff : System RAM
+  01000000-01e00e36 : Kernel code
+";
+        assert_eq!(
+            open_text("iomem", text),
+            [(0x1000, 0x9ec00, 0x1000), (0x100000, 0x7fee0000, 0x100000)]
+        );
+    }
+}
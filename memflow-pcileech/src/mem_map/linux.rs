@@ -0,0 +1,353 @@
+//! Parsers for memory maps obtained from Linux targets.
+//!
+//! Supported are the contents of `/proc/iomem`, `BIOS-e820` lines from the kernel log
+//! and dumps of `/sys/firmware/memmap` (either the directory itself or the output of
+//! `grep . /sys/firmware/memmap/*/*`).
+use std::collections::BTreeMap;
+use std::path::Path;
+
+use memflow::prelude::v1::*;
+
+use super::{mapping, parse_hex};
+
+const SYSTEM_RAM: &str = "System RAM";
+
+/// Returns true if the text contains `BIOS-e820` lines from the kernel log.
+pub(crate) fn is_e820(text: &str) -> bool {
+    text.contains("BIOS-e820:")
+}
+
+/// Parses all `usable` ranges of the `BIOS-e820` lines in a kernel log.
+pub(crate) fn parse_e820(text: &str) -> Result<Vec<PhysicalMemoryMapping>> {
+    let ranges = text
+        .lines()
+        .filter_map(parse_e820_line)
+        .filter(|(_, _, kind)| *kind == "usable")
+        .map(|(start, end, _)| (start, end));
+    build_mappings(ranges, "e820")
+}
+
+fn parse_e820_line(line: &str) -> Option<(u64, u64, &str)> {
+    let pos = line.find("BIOS-e820:")?;
+    let entry = line[pos + "BIOS-e820:".len()..].trim();
+    if let Some(entry) = entry.strip_prefix("[mem ") {
+        // BIOS-e820: [mem 0x0000000000000000-0x000000000009fbff] usable
+        let (range, kind) = entry.split_once(']')?;
+        let (start, end) = range.split_once('-')?;
+        Some((
+            parse_hex(start)?,
+            parse_hex(end)?.checked_add(1)?,
+            kind.trim(),
+        ))
+    } else {
+        // older kernels: BIOS-e820: 0000000000000000 - 000000000009fc00 (usable)
+        let (range, kind) = entry.split_once('(')?;
+        let (start, end) = range.split_once('-')?;
+        Some((
+            parse_hex(start)?,
+            parse_hex(end)?,
+            kind.trim().trim_end_matches(')'),
+        ))
+    }
+}
+
+/// Returns true if the text looks like the contents of `/proc/iomem`.
+pub(crate) fn is_iomem(text: &str) -> bool {
+    let mut lines = text
+        .lines()
+        .filter(|line| !line.trim().is_empty())
+        .peekable();
+    lines.peek().is_some() && lines.all(|line| parse_iomem_line(line.trim()).is_some())
+}
+
+/// Parses all top-level `System RAM` ranges of `/proc/iomem`.
+pub(crate) fn parse_iomem(text: &str) -> Result<Vec<PhysicalMemoryMapping>> {
+    let ranges = text
+        .lines()
+        // nested resources are indented and always part of a top-level range
+        .filter(|line| !line.starts_with(char::is_whitespace))
+        .filter_map(parse_iomem_line)
+        .filter(|(_, _, name)| *name == SYSTEM_RAM)
+        .map(|(start, end, _)| (start, end))
+        .collect::<Vec<_>>();
+
+    // /proc/iomem only shows zeroed addresses to unprivileged users
+    if !ranges.is_empty() && ranges.iter().all(|&(start, end)| start == 0 && end == 1) {
+        return Err(
+            Error(ErrorOrigin::Connector, ErrorKind::Configuration).log_error(
+                "all addresses in /proc/iomem are zero, it has to be read as root on the target",
+            ),
+        );
+    }
+
+    build_mappings(ranges.into_iter(), "/proc/iomem")
+}
+
+fn parse_iomem_line(line: &str) -> Option<(u64, u64, &str)> {
+    // 00100000-7ffdffff : System RAM
+    let (range, name) = line.split_once(" : ")?;
+    let (start, end) = range.split_once('-')?;
+    Some((
+        parse_hex(start)?,
+        parse_hex(end)?.checked_add(1)?,
+        name.trim(),
+    ))
+}
+
+/// Returns true if the text is a dump of `/sys/firmware/memmap` as written by `grep . /sys/firmware/memmap/*/*`.
+pub(crate) fn is_sysfs_dump(text: &str) -> bool {
+    text.lines().any(|line| parse_sysfs_line(line).is_some())
+}
+
+/// Parses all `System RAM` entries of a `/sys/firmware/memmap` dump.
+pub(crate) fn parse_sysfs_dump(text: &str) -> Result<Vec<PhysicalMemoryMapping>> {
+    let mut entries = BTreeMap::new();
+    for (index, field, value) in text.lines().filter_map(parse_sysfs_line) {
+        let entry = entries.entry(index).or_insert((None, None, None));
+        match field {
+            "start" => entry.0 = parse_hex(value),
+            "end" => entry.1 = parse_hex(value),
+            "type" => entry.2 = Some(value.to_string()),
+            _ => (),
+        }
+    }
+    parse_sysfs_entries(entries.into_values())
+}
+
+fn parse_sysfs_line(line: &str) -> Option<(u64, &str, &str)> {
+    // /sys/firmware/memmap/0/start:0x0
+    let (path, value) = line.trim().split_once(':')?;
+    let mut components = path.rsplit('/');
+    let field = components.next()?;
+    let index = components.next()?.parse().ok()?;
+    if components.next()? != "memmap" {
+        return None;
+    }
+    Some((index, field, value.trim()))
+}
+
+/// Reads all `System RAM` entries from a copy of the `/sys/firmware/memmap` directory.
+pub(crate) fn open_sysfs_dir<P: AsRef<Path>>(path: P) -> Result<Vec<PhysicalMemoryMapping>> {
+    let read_dir_err = |err: std::io::Error| {
+        Error(ErrorOrigin::Connector, ErrorKind::UnableToReadDir).log_error(format!(
+            "unable to read memory map directory {}: {}",
+            path.as_ref().to_string_lossy(),
+            err
+        ))
+    };
+
+    let mut entries = BTreeMap::new();
+    for dir in std::fs::read_dir(path.as_ref()).map_err(read_dir_err)? {
+        let dir = dir.map_err(read_dir_err)?.path();
+        let index = match dir
+            .file_name()
+            .and_then(|name| name.to_str())
+            .and_then(|name| name.parse::<u64>().ok())
+        {
+            Some(index) => index,
+            None => continue,
+        };
+
+        let read_field = |field: &str| {
+            std::fs::read_to_string(dir.join(field))
+                .ok()
+                .map(|value| value.trim().to_string())
+        };
+        entries.insert(
+            index,
+            (
+                read_field("start").as_deref().and_then(parse_hex),
+                read_field("end").as_deref().and_then(parse_hex),
+                read_field("type"),
+            ),
+        );
+    }
+    parse_sysfs_entries(entries.into_values())
+}
+
+fn parse_sysfs_entries(
+    entries: impl Iterator<Item = (Option<u64>, Option<u64>, Option<String>)>,
+) -> Result<Vec<PhysicalMemoryMapping>> {
+    let ranges = entries.filter_map(|entry| match entry {
+        (Some(start), Some(end), Some(kind)) if kind == SYSTEM_RAM => {
+            Some((start, end.checked_add(1)?))
+        }
+        _ => None,
+    });
+    build_mappings(ranges, "/sys/firmware/memmap")
+}
+
+/// Builds the mappings for the given `start..end` ranges.
+fn build_mappings(
+    ranges: impl Iterator<Item = (u64, u64)>,
+    source: &str,
+) -> Result<Vec<PhysicalMemoryMapping>> {
+    let mappings = ranges
+        .filter(|(start, end)| end > start)
+        .map(|(start, end)| mapping(start, end - start, start))
+        .collect::<Vec<_>>();
+
+    if !mappings.is_empty() {
+        Ok(mappings)
+    } else {
+        Err(Error(ErrorOrigin::Connector, ErrorKind::Configuration)
+            .log_error(format!("no usable memory ranges found in {source}")))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ranges(mappings: &[PhysicalMemoryMapping]) -> Vec<(u64, u64)> {
+        mappings
+            .iter()
+            .map(|m| {
+                assert_eq!(m.base, m.real_base);
+                (m.base.to_umem(), m.size)
+            })
+            .collect()
+    }
+
+    #[test]
+    fn parse_e820_inclusive() {
+        let text = "\
+[    0.000000] BIOS-provided physical RAM map:
+[    0.000000] BIOS-e820: [mem 0x0000000000000000-0x000000000009fbff] usable
+[    0.000000] BIOS-e820: [mem 0x000000000009fc00-0x000000000009ffff] reserved
+[    0.000000] BIOS-e820: [mem 0x00000000000f0000-0x00000000000fffff] reserved
+[    0.000000] BIOS-e820: [mem 0x0000000000100000-0x000000007ffdffff] usable
+[    0.000000] BIOS-e820: [mem 0x000000007ffe0000-0x000000007fffffff] reserved
+[    0.000000] BIOS-e820: [mem 0x00000000feffc000-0x00000000feffffff] reserved
+[    0.000000] BIOS-e820: [mem 0x00000000fffc0000-0x00000000ffffffff] reserved
+[    0.000000] BIOS-e820: [mem 0x0000000100000000-0x000000017fffffff] usable
+[    0.000000] NX (Execute Disable) protection: active
+";
+        assert!(is_e820(text));
+        assert_eq!(
+            ranges(&parse_e820(text).unwrap()),
+            [
+                (0x0, 0x9fc00),
+                (0x100000, 0x7fee0000),
+                (0x1_0000_0000, 0x8000_0000)
+            ]
+        );
+    }
+
+    #[test]
+    fn parse_e820_exclusive() {
+        let text = "\
+[    0.000000] BIOS-provided physical RAM map:
+[    0.000000]  BIOS-e820: 0000000000000000 - 000000000009fc00 (usable)
+[    0.000000]  BIOS-e820: 000000000009fc00 - 00000000000a0000 (reserved)
+[    0.000000]  BIOS-e820: 00000000000e8000 - 0000000000100000 (reserved)
+[    0.000000]  BIOS-e820: 0000000000100000 - 000000007fff0000 (usable)
+[    0.000000]  BIOS-e820: 000000007fff0000 - 0000000080000000 (ACPI data)
+[    0.000000]  BIOS-e820: 00000000fffbc000 - 0000000100000000 (reserved)
+";
+        assert!(is_e820(text));
+        assert_eq!(
+            ranges(&parse_e820(text).unwrap()),
+            [(0x0, 0x9fc00), (0x100000, 0x7fef0000)]
+        );
+    }
+
+    #[test]
+    fn parse_iomem_top_level() {
+        let text = "\
+00000000-00000fff : Reserved
+00001000-0009fbff : System RAM
+0009fc00-0009ffff : Reserved
+000a0000-000bffff : PCI Bus 0000:00
+000f0000-000fffff : Reserved
+  000f0000-000fffff : System ROM
+00100000-7ffdffff : System RAM
+  01000000-01e00e36 : Kernel code
+  01e00e37-0264b93f : Kernel data
+  02d2a000-031fffff : Kernel bss
+7ffe0000-7fffffff : Reserved
+fec00000-fec003ff : IOAPIC 0
+100000000-17fffffff : System RAM
+";
+        assert!(is_iomem(text));
+        assert_eq!(
+            ranges(&parse_iomem(text).unwrap()),
+            [
+                (0x1000, 0x9ec00),
+                (0x100000, 0x7fee0000),
+                (0x1_0000_0000, 0x8000_0000)
+            ]
+        );
+    }
+
+    #[test]
+    fn reject_unprivileged_iomem() {
+        let text = "\
+00000000-00000000 : Reserved
+00000000-00000000 : System RAM
+00000000-00000000 : Reserved
+00000000-00000000 : System RAM
+  00000000-00000000 : Kernel code
+  00000000-00000000 : Kernel data
+";
+        assert!(is_iomem(text));
+        assert!(parse_iomem(text).is_err());
+    }
+
+    const SYSFS_ENTRIES: [(&str, &str, &str, &str); 5] = [
+        ("0", "0x0", "0x9fbff", "System RAM"),
+        ("1", "0x9fc00", "0x9ffff", "Reserved"),
+        ("2", "0xf0000", "0xfffff", "Reserved"),
+        ("3", "0x100000", "0x7ffdffff", "System RAM"),
+        ("10", "0x100000000", "0x17fffffff", "System RAM"),
+    ];
+
+    const SYSFS_RANGES: [(u64, u64); 3] = [
+        (0x0, 0x9fc00),
+        (0x100000, 0x7fee0000),
+        (0x1_0000_0000, 0x8000_0000),
+    ];
+
+    #[test]
+    fn parse_sysfs_grep_dump() {
+        // `grep . /sys/firmware/memmap/*/*` sorts the entries lexically
+        let text = "\
+/sys/firmware/memmap/0/end:0x9fbff
+/sys/firmware/memmap/0/start:0x0
+/sys/firmware/memmap/0/type:System RAM
+/sys/firmware/memmap/1/end:0x9ffff
+/sys/firmware/memmap/1/start:0x9fc00
+/sys/firmware/memmap/1/type:Reserved
+/sys/firmware/memmap/10/end:0x17fffffff
+/sys/firmware/memmap/10/start:0x100000000
+/sys/firmware/memmap/10/type:System RAM
+/sys/firmware/memmap/2/end:0xfffff
+/sys/firmware/memmap/2/start:0xf0000
+/sys/firmware/memmap/2/type:Reserved
+/sys/firmware/memmap/3/end:0x7ffdffff
+/sys/firmware/memmap/3/start:0x100000
+/sys/firmware/memmap/3/type:System RAM
+";
+        assert!(is_sysfs_dump(text));
+        assert!(!is_iomem(text));
+        assert_eq!(ranges(&parse_sysfs_dump(text).unwrap()), SYSFS_RANGES);
+    }
+
+    #[test]
+    fn open_sysfs_copy() {
+        let dir = std::env::temp_dir().join("memflow-pcileech-sysfs-memmap");
+        let _ = std::fs::remove_dir_all(&dir);
+        for (index, start, end, kind) in SYSFS_ENTRIES.iter() {
+            let entry = dir.join(index);
+            std::fs::create_dir_all(&entry).unwrap();
+            // sysfs attributes end with a newline
+            std::fs::write(entry.join("start"), format!("{start}\n")).unwrap();
+            std::fs::write(entry.join("end"), format!("{end}\n")).unwrap();
+            std::fs::write(entry.join("type"), format!("{kind}\n")).unwrap();
+        }
+        // entries that are not numbered are ignored
+        std::fs::create_dir_all(dir.join("power")).unwrap();
+
+        assert_eq!(ranges(&open_sysfs_dir(&dir).unwrap()), SYSFS_RANGES);
+    }
+}
@@ -0,0 +1,135 @@
+//! Policies for combining the memory map of the user with the one provided by the os layer.
+use std::fmt;
+use std::str::FromStr;
+
+use memflow::prelude::v1::*;
+
+use super::mapping;
+
+/// Decides how the memory map provided by the user (e.g. via the `memmap` argument)
+/// and the memory map provided by the os layer (via `set_mem_map`) are combined.
+///
+/// In all cases the map that is available is used as long as only one of them has been provided.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum MemMapPolicy {
+    /// The user map always takes precedence over the os map.
+    #[default]
+    UserOnly,
+    /// The os map always takes precedence over the user map.
+    OsOnly,
+    /// Only ranges that are contained in both maps are used.
+    Intersect,
+    /// All ranges of both maps are used.
+    Union,
+    /// The most recently provided map is used.
+    Replace,
+}
+
+impl MemMapPolicy {
+    /// Combines the user and os map according to this policy.
+    ///
+    /// The translation of the user map (`real_base`) is used for all ranges contained in both maps.
+    pub(crate) fn combine(
+        self,
+        user: Option<&[PhysicalMemoryMapping]>,
+        os: Option<&[PhysicalMemoryMapping]>,
+        os_latest: bool,
+    ) -> Option<Vec<PhysicalMemoryMapping>> {
+        let (user, os) = match (user, os) {
+            (Some(user), Some(os)) => (user, os),
+            (user, os) => return user.or(os).map(<[_]>::to_vec),
+        };
+
+        Some(match self {
+            MemMapPolicy::UserOnly => user.to_vec(),
+            MemMapPolicy::OsOnly => os.to_vec(),
+            MemMapPolicy::Intersect => intersect(user, os),
+            MemMapPolicy::Union => union(user, os),
+            MemMapPolicy::Replace if os_latest => os.to_vec(),
+            MemMapPolicy::Replace => user.to_vec(),
+        })
+    }
+}
+
+impl FromStr for MemMapPolicy {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "user-only" => Ok(MemMapPolicy::UserOnly),
+            "os-only" => Ok(MemMapPolicy::OsOnly),
+            "intersect" => Ok(MemMapPolicy::Intersect),
+            "union" => Ok(MemMapPolicy::Union),
+            "replace" => Ok(MemMapPolicy::Replace),
+            _ => Err(Error(ErrorOrigin::Connector, ErrorKind::ArgValidation).log_error(format!(
+                "invalid memory map policy `{s}`, valid policies are: user-only, os-only, intersect, union, replace"
+            ))),
+        }
+    }
+}
+
+impl fmt::Display for MemMapPolicy {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            MemMapPolicy::UserOnly => "user-only",
+            MemMapPolicy::OsOnly => "os-only",
+            MemMapPolicy::Intersect => "intersect",
+            MemMapPolicy::Union => "union",
+            MemMapPolicy::Replace => "replace",
+        })
+    }
+}
+
+/// Returns the `(base, end, real_base)` triple of a mapping.
+fn bounds(mapping: &PhysicalMemoryMapping) -> (u64, u64, u64) {
+    let base = mapping.base.to_umem();
+    (base, base + mapping.size, mapping.real_base.to_umem())
+}
+
+/// Returns all ranges that are contained in both maps.
+fn intersect(
+    user: &[PhysicalMemoryMapping],
+    os: &[PhysicalMemoryMapping],
+) -> Vec<PhysicalMemoryMapping> {
+    let mut mappings = Vec::new();
+    for (base, end, real_base) in user.iter().map(bounds) {
+        for (os_base, os_end, _) in os.iter().map(bounds) {
+            let start = base.max(os_base);
+            let stop = end.min(os_end);
+            if start < stop {
+                mappings.push(mapping(start, stop - start, real_base + (start - base)));
+            }
+        }
+    }
+    mappings
+}
+
+/// Returns all ranges of the user map and the parts of the os map not covered by it.
+fn union(
+    user: &[PhysicalMemoryMapping],
+    os: &[PhysicalMemoryMapping],
+) -> Vec<PhysicalMemoryMapping> {
+    let mut covered = user.iter().map(bounds).collect::<Vec<_>>();
+    covered.sort_unstable();
+
+    let mut mappings = user.to_vec();
+    for (os_base, os_end, os_real_base) in os.iter().map(bounds) {
+        let mut push = |start: u64, stop: u64| {
+            if start < stop {
+                mappings.push(mapping(
+                    start,
+                    stop - start,
+                    os_real_base + (start - os_base),
+                ));
+            }
+        };
+
+        let mut cursor = os_base;
+        for &(base, end, _) in covered.iter().filter(|r| r.1 > os_base && r.0 < os_end) {
+            push(cursor, base.min(os_end));
+            cursor = cursor.max(end);
+        }
+        push(cursor, os_end);
+    }
+    mappings
+}
@@ -0,0 +1,127 @@
+//! Discovery of the memory map by probing the physical address space of the target.
+use std::ffi::c_void;
+
+use parking_lot::Mutex;
+
+use memflow::prelude::v1::*;
+
+use leechcore_sys::*;
+
+use super::mapping;
+use crate::handle::LcHandle;
+
+const PAGE_SIZE: u64 = 0x1000;
+
+/// Number of pages that are probed at once if the device does not limit it.
+const PROBE_CHUNK_PAGES: u64 = 0x400;
+
+/// Probes all pages up to `LC_OPT_CORE_ADDR_MAX` and returns the readable ranges.
+///
+/// FPGA devices are probed via `LC_CMD_FPGA_PROBE`, all other devices via scatter reads.
+/// The handle is only locked for a single chunk at a time so clones of the connector
+/// remain usable during the sweep.
+/// `progress` is called with the number of bytes probed so far and the total number of bytes.
+pub(crate) fn probe(
+    handle: &Mutex<LcHandle>,
+    progress: &mut dyn FnMut(u64, u64),
+) -> Result<Vec<PhysicalMemoryMapping>> {
+    let (addr_max, fpga_max_pages) = {
+        let handle = handle.lock();
+        let addr_max = handle
+            .get_option(LC_OPT_CORE_ADDR_MAX)
+            .filter(|&addr_max| addr_max > 0)
+            .ok_or_else(|| {
+                Error(ErrorOrigin::Connector, ErrorKind::NotSupported)
+                    .log_error("unable to determine the maximum address of the leechcore device")
+            })?;
+
+        // the probe command is only available on fpga devices
+        let fpga_max_pages = handle.get_option(LC_OPT_FPGA_FPGA_ID).map(|_| {
+            handle
+                .get_option(LC_OPT_FPGA_PROBE_MAXPAGES)
+                .filter(|&pages| pages > 0)
+                .unwrap_or(PROBE_CHUNK_PAGES)
+        });
+        (addr_max, fpga_max_pages)
+    };
+
+    let total_pages = addr_max.div_ceil(PAGE_SIZE);
+    let chunk_pages = fpga_max_pages.unwrap_or(PROBE_CHUNK_PAGES);
+
+    let mut ranges = Vec::new();
+    let mut range_start = None;
+    let mut page = 0;
+    while page < total_pages {
+        let count = chunk_pages.min(total_pages - page);
+        let readable = {
+            let handle = handle.lock();
+            if fpga_max_pages.is_some() {
+                probe_fpga(&handle, page * PAGE_SIZE, count)?
+            } else {
+                probe_scatter(&handle, page * PAGE_SIZE, count)?
+            }
+        };
+
+        for (idx, readable) in readable.into_iter().enumerate() {
+            let addr = (page + idx as u64) * PAGE_SIZE;
+            match (readable, range_start) {
+                (true, None) => range_start = Some(addr),
+                (false, Some(start)) => {
+                    ranges.push(mapping(start, addr - start, start));
+                    range_start = None;
+                }
+                _ => (),
+            }
+        }
+
+        page += count;
+        progress(page * PAGE_SIZE, total_pages * PAGE_SIZE);
+    }
+
+    if let Some(start) = range_start {
+        ranges.push(mapping(start, total_pages * PAGE_SIZE - start, start));
+    }
+
+    if ranges.is_empty() {
+        return Err(Error(ErrorOrigin::Connector, ErrorKind::NotFound)
+            .log_error("probing did not find any readable memory"));
+    }
+    Ok(ranges)
+}
+
+/// Probes the pages via `LC_CMD_FPGA_PROBE`.
+///
+/// The number of pages is passed in the lower bits of the command and the start address as input,
+/// the device returns one byte per page which is non-zero for readable pages.
+fn probe_fpga(handle: &LcHandle, addr: u64, count: u64) -> Result<Vec<bool>> {
+    let result = handle
+        .command(LC_CMD_FPGA_PROBE | count, &addr.to_le_bytes())
+        .filter(|result| result.len() as u64 >= count)
+        .ok_or_else(|| {
+            Error(ErrorOrigin::Connector, ErrorKind::NotSupported)
+                .log_error(format!("unable to probe memory at {addr:#x}"))
+        })?;
+    Ok(result[..count as usize].iter().map(|&r| r != 0).collect())
+}
+
+/// Probes the pages by reading them and checking which reads succeeded.
+fn probe_scatter(handle: &LcHandle, addr: u64, count: u64) -> Result<Vec<bool>> {
+    let mut mems = std::ptr::null_mut::<PMEM_SCATTER>();
+    if unsafe { LcAllocScatter1(count as u32, &mut mems) } != 1 {
+        return Err(Error(ErrorOrigin::Connector, ErrorKind::InvalidMemorySize)
+            .log_error("unable to allocate scatter buffer"));
+    }
+
+    let readable = unsafe {
+        for i in 0..count as usize {
+            (**mems.add(i)).qwA = addr + i as u64 * PAGE_SIZE;
+        }
+        LcReadScatter(handle.as_raw(), count as u32, mems);
+        (0..count as usize)
+            .map(|i| (**mems.add(i)).f != 0)
+            .collect()
+    };
+
+    unsafe { LcMemFree(mems as *mut c_void) };
+    Ok(readable)
+}
@@ -0,0 +1,264 @@
+//! Parser for memory maps exported from the Windows registry.
+//!
+//! The physical memory ranges of a Windows machine are stored as a binary `CM_RESOURCE_LIST` under
+//! `HKLM\HARDWARE\RESOURCEMAP\System Resources\Physical Memory\.Translated`.
+//! The key can be exported via `reg export` and is decoded here.
+use memflow::prelude::v1::*;
+
+use super::mapping;
+
+const VALUE_NAME: &str = "\".Translated\"=hex(8):";
+
+const CM_RESOURCE_TYPE_MEMORY: u8 = 3;
+const CM_RESOURCE_TYPE_MEMORY_LARGE: u8 = 7;
+
+const CM_RESOURCE_MEMORY_LARGE_40: u16 = 0x200;
+const CM_RESOURCE_MEMORY_LARGE_48: u16 = 0x400;
+const CM_RESOURCE_MEMORY_LARGE_64: u16 = 0x800;
+
+// CM_PARTIAL_RESOURCE_DESCRIPTOR is packed to 4 bytes,
+// its union only contains 8 byte members on 64-bit windows
+const CM_PARTIAL_RESOURCE_DESCRIPTOR_SIZES: [usize; 2] = [20, 16];
+
+/// Returns true if the text is a registry export.
+pub(crate) fn is_registry_export(text: &str) -> bool {
+    let text = text.trim_start_matches('\u{feff}').trim_start();
+    text.starts_with("Windows Registry Editor") || text.starts_with("REGEDIT4")
+}
+
+/// Parses the `.Translated` value of a registry export into a list of mappings.
+pub(crate) fn parse(text: &str) -> Result<Vec<PhysicalMemoryMapping>> {
+    let data = find_value(text)?;
+    parse_resource_list(&data)
+}
+
+/// Finds the `.Translated` value and decodes its hex blob.
+fn find_value(text: &str) -> Result<Vec<u8>> {
+    // long values are split across multiple lines which end with a backslash
+    let mut value = None;
+    let mut entry = String::new();
+    for line in text.lines() {
+        let line = line.trim();
+        entry.push_str(line.trim_end_matches('\\'));
+        if line.ends_with('\\') {
+            continue;
+        }
+
+        if let Some(hex) = entry.strip_prefix(VALUE_NAME) {
+            value = Some(hex.to_string());
+            break;
+        }
+        entry.clear();
+    }
+
+    let value = value.ok_or_else(|| {
+        Error(ErrorOrigin::Connector, ErrorKind::Configuration)
+            .log_error("registry export does not contain a `.Translated` resource list")
+    })?;
+
+    value
+        .split(',')
+        .map(str::trim)
+        .filter(|byte| !byte.is_empty())
+        .map(|byte| {
+            u8::from_str_radix(byte, 16).map_err(|_| {
+                Error(ErrorOrigin::Connector, ErrorKind::Configuration)
+                    .log_error(format!("invalid byte `{byte}` in registry export"))
+            })
+        })
+        .collect()
+}
+
+/// Decodes a binary `CM_RESOURCE_LIST` into a list of mappings.
+fn parse_resource_list(data: &[u8]) -> Result<Vec<PhysicalMemoryMapping>> {
+    let descriptor_size = CM_PARTIAL_RESOURCE_DESCRIPTOR_SIZES
+        .iter()
+        .copied()
+        .find(|&size| resource_list_len(data, size) == Some(data.len()))
+        .ok_or_else(|| {
+            Error(ErrorOrigin::Connector, ErrorKind::Configuration).log_error(format!(
+                "registry resource list of {} bytes does not match its descriptor counts",
+                data.len()
+            ))
+        })?;
+
+    let mut reader = Reader { data, pos: 0 };
+    let mut mappings = Vec::new();
+
+    let count = reader.u32()?;
+    for _ in 0..count {
+        // CM_FULL_RESOURCE_DESCRIPTOR
+        let _interface_type = reader.u32()?;
+        let _bus_number = reader.u32()?;
+
+        // CM_PARTIAL_RESOURCE_LIST
+        let _version = reader.u16()?;
+        let _revision = reader.u16()?;
+        let partial_count = reader.u32()?;
+
+        for _ in 0..partial_count {
+            let desc = reader.bytes(descriptor_size)?;
+            let resource_type = desc[0];
+            let flags = u16::from_le_bytes([desc[2], desc[3]]);
+            let start = u64::from_le_bytes([
+                desc[4], desc[5], desc[6], desc[7], desc[8], desc[9], desc[10], desc[11],
+            ]);
+            let length = u32::from_le_bytes([desc[12], desc[13], desc[14], desc[15]]) as u64;
+
+            let length = match resource_type {
+                CM_RESOURCE_TYPE_MEMORY => length,
+                CM_RESOURCE_TYPE_MEMORY_LARGE if flags & CM_RESOURCE_MEMORY_LARGE_40 != 0 => {
+                    length << 8
+                }
+                CM_RESOURCE_TYPE_MEMORY_LARGE if flags & CM_RESOURCE_MEMORY_LARGE_48 != 0 => {
+                    length << 16
+                }
+                CM_RESOURCE_TYPE_MEMORY_LARGE if flags & CM_RESOURCE_MEMORY_LARGE_64 != 0 => {
+                    length << 32
+                }
+                _ => continue,
+            };
+
+            if length > 0 {
+                mappings.push(mapping(start, length, start));
+            }
+        }
+    }
+
+    Ok(mappings)
+}
+
+/// Computes the length of a `CM_RESOURCE_LIST` assuming the given partial descriptor size.
+///
+/// The layout differs between 32-bit and 64-bit windows,
+/// only the correct descriptor size makes the descriptor counts match the length of the blob.
+fn resource_list_len(data: &[u8], descriptor_size: usize) -> Option<usize> {
+    let u32_at = |pos: usize| {
+        let bytes = data.get(pos..pos + 4)?;
+        Some(u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]) as usize)
+    };
+
+    let mut pos = 4;
+    for _ in 0..u32_at(0)? {
+        // interface type, bus number, version and revision precede the partial count
+        let partial_count = u32_at(pos + 12)?;
+        pos = pos
+            .checked_add(16)?
+            .checked_add(partial_count.checked_mul(descriptor_size)?)?;
+    }
+    Some(pos)
+}
+
+struct Reader<'a> {
+    data: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn bytes(&mut self, len: usize) -> Result<&'a [u8]> {
+        let bytes = self.data.get(self.pos..self.pos + len).ok_or_else(|| {
+            Error(ErrorOrigin::Connector, ErrorKind::Configuration)
+                .log_error("registry resource list is truncated")
+        })?;
+        self.pos += len;
+        Ok(bytes)
+    }
+
+    fn u16(&mut self) -> Result<u16> {
+        let bytes = self.bytes(2)?;
+        Ok(u16::from_le_bytes([bytes[0], bytes[1]]))
+    }
+
+    fn u32(&mut self) -> Result<u32> {
+        let bytes = self.bytes(4)?;
+        Ok(u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::mem_map::decode_text;
+
+    // excerpt of `reg export "HKLM\HARDWARE\RESOURCEMAP\System Resources\Physical Memory"`
+    const EXPORT_X64: &str = r#"Windows Registry Editor Version 5.00
+
+[HKEY_LOCAL_MACHINE\HARDWARE\RESOURCEMAP\System Resources\Physical Memory]
+".Translated"=hex(8):01,00,00,00,00,00,00,00,00,00,00,00,01,00,01,00,03,00,\
+  00,00,03,01,00,00,00,10,00,00,00,00,00,00,00,e0,09,00,00,00,00,00,03,01,00,\
+  00,00,00,10,00,00,00,00,00,00,00,f0,01,00,00,00,00,07,01,00,02,00,00,00,00,\
+  01,00,00,00,00,00,40,00,00,00,00,00
+
+"#;
+
+    // the same ranges exported on 32-bit windows
+    const EXPORT_X86: &str = r#"Windows Registry Editor Version 5.00
+
+[HKEY_LOCAL_MACHINE\HARDWARE\RESOURCEMAP\System Resources\Physical Memory]
+".Translated"=hex(8):01,00,00,00,00,00,00,00,00,00,00,00,01,00,01,00,03,00,\
+  00,00,03,01,00,00,00,10,00,00,00,00,00,00,00,e0,09,00,03,01,00,00,00,00,10,\
+  00,00,00,00,00,00,00,f0,01,07,01,00,02,00,00,00,00,01,00,00,00,00,00,40,00
+
+"#;
+
+    fn expected() -> Vec<(Address, umem, Address)> {
+        [
+            (0x1000u64, 0x9e000),
+            (0x100000, 0x1f00000),
+            (0x1_0000_0000, 0x4000_0000),
+        ]
+        .iter()
+        .map(|&(base, size)| (Address::from(base), size, Address::from(base)))
+        .collect()
+    }
+
+    fn ranges(mappings: &[PhysicalMemoryMapping]) -> Vec<(Address, umem, Address)> {
+        mappings
+            .iter()
+            .map(|mapping| (mapping.base, mapping.size, mapping.real_base))
+            .collect()
+    }
+
+    #[test]
+    fn parse_x64_export() {
+        assert!(is_registry_export(EXPORT_X64));
+        let mappings = parse(EXPORT_X64).unwrap();
+        assert_eq!(ranges(&mappings), expected());
+    }
+
+    #[test]
+    fn parse_x86_export() {
+        let mappings = parse(EXPORT_X86).unwrap();
+        assert_eq!(ranges(&mappings), expected());
+    }
+
+    #[test]
+    fn join_continuation_lines() {
+        let data = find_value(EXPORT_X64).unwrap();
+        assert_eq!(data.len(), 4 + 16 + 3 * 20);
+        assert_eq!(&data[..4], &[1, 0, 0, 0]);
+        assert_eq!(&data[data.len() - 4..], &[0, 0, 0, 0]);
+    }
+
+    #[test]
+    fn reject_mismatching_length() {
+        let mut data = find_value(EXPORT_X64).unwrap();
+        data.push(0);
+        assert!(parse_resource_list(&data).is_err());
+        data.truncate(data.len() - 2);
+        assert!(parse_resource_list(&data).is_err());
+    }
+
+    #[test]
+    fn decode_utf16_export() {
+        // `reg export` writes UTF-16LE files with a byte order mark
+        let mut data = vec![0xff, 0xfe];
+        data.extend(EXPORT_X64.encode_utf16().flat_map(u16::to_le_bytes));
+
+        let text = decode_text(&data);
+        assert_eq!(text, EXPORT_X64);
+        assert!(is_registry_export(&text));
+        assert_eq!(ranges(&parse(&text).unwrap()), expected());
+    }
+}
@@ -0,0 +1,175 @@
+//! Parser and writer for the plain text memory map format of LeechCore.
+//!
+//! This format is used by pcileech and MemProcFS, each line contains one range:
+//! ```text
+//! 0000      1000 -    9efff
+//! 0001    100000 - 7fffffff -> 200000000
+//! ```
+//! The leading index and the trailing remap address are optional, the end of a range is inclusive.
+use std::fmt::Write;
+
+use memflow::prelude::v1::*;
+
+use super::{mapping, parse_hex, sorted_ranges};
+
+/// Returns true if all lines of the text are LeechCore memory map entries.
+pub(crate) fn is_text_mem_map(text: &str) -> bool {
+    let mut lines = entry_lines(text).peekable();
+    lines.peek().is_some() && lines.all(|line| parse_line(line).is_some())
+}
+
+/// Parses a LeechCore text memory map.
+pub(crate) fn parse(text: &str) -> Result<Vec<PhysicalMemoryMapping>> {
+    let mut mappings = Vec::new();
+    for (nr, line) in entry_lines(text).enumerate() {
+        let (start, end, remap) = parse_line(line).ok_or_else(|| {
+            Error(ErrorOrigin::Connector, ErrorKind::Configuration)
+                .log_error(format!("invalid memory map entry {nr}: {line}"))
+        })?;
+        if end < start {
+            return Err(
+                Error(ErrorOrigin::Connector, ErrorKind::Configuration).log_error(format!(
+                    "memory map entry {nr} ends before it starts: {line}"
+                )),
+            );
+        }
+        mappings.push(mapping(start, end - start + 1, remap.unwrap_or(start)));
+    }
+    Ok(mappings)
+}
+
+/// Returns all lines that are neither empty nor comments.
+fn entry_lines(text: &str) -> impl Iterator<Item = &str> {
+    text.lines()
+        .map(|line| line.trim())
+        .filter(|line| !line.is_empty() && !line.starts_with('#') && !line.starts_with("//"))
+}
+
+/// Parses a single line into its start, inclusive end and optional remap address.
+fn parse_line(line: &str) -> Option<(u64, u64, Option<u64>)> {
+    // the range is always separated by a dash, everything in front of it is the optional index
+    let (head, tail) = line.split_once(" - ").or_else(|| line.split_once('-'))?;
+    if head.split_whitespace().count() > 2 {
+        return None;
+    }
+    let start = parse_hex(head.split_whitespace().last()?)?;
+
+    let mut tail = tail.split_whitespace();
+    let end = parse_hex(tail.next()?)?;
+    let remap = match tail.next() {
+        Some("->") => Some(parse_hex(tail.next()?)?),
+        Some(remap) => Some(parse_hex(remap)?),
+        None => None,
+    };
+    if tail.next().is_some() {
+        return None;
+    }
+    Some((start, end, remap))
+}
+
+/// Writes the memory map in the LeechCore text format.
+pub(crate) fn format(mem_map: &MemoryMap<(Address, umem)>) -> String {
+    let mut text = String::new();
+    for (idx, (base, size, real_base)) in sorted_ranges(mem_map).into_iter().enumerate() {
+        let end = base + size - 1;
+        if base == real_base {
+            writeln!(text, "{idx:04x} {base:>16x} - {end:>16x}").ok();
+        } else {
+            writeln!(text, "{idx:04x} {base:>16x} - {end:>16x} -> {real_base:x}").ok();
+        }
+    }
+    text
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ranges(mappings: &[PhysicalMemoryMapping]) -> Vec<(u64, u64, u64)> {
+        mappings
+            .iter()
+            .map(|m| (m.base.to_umem(), m.size, m.real_base.to_umem()))
+            .collect()
+    }
+
+    #[test]
+    fn round_trip() {
+        let mut mem_map = MemoryMap::new();
+        mem_map.push_remap(
+            Address::from(0x100000),
+            0x7ff00000,
+            Address::from(0x200000000u64),
+        );
+        mem_map.push_remap(Address::from(0x1000), 0x9e000, Address::from(0x1000));
+        mem_map.push_remap(
+            Address::from(0x100000000u64),
+            0x1000,
+            Address::from(0x100000000u64),
+        );
+
+        let text = format(&mem_map);
+        assert_eq!(
+            text,
+            "\
+0000             1000 -            9efff
+0001           100000 -         7fffffff -> 200000000
+0002        100000000 -        100000fff
+"
+        );
+        assert!(is_text_mem_map(&text));
+        assert_eq!(ranges(&parse(&text).unwrap()), sorted_ranges(&mem_map));
+    }
+
+    #[test]
+    fn parse_remap_variants() {
+        let text = "\
+0000      1000 -    9efff
+0001    100000 - 7fffffff -> 200000000
+0002 100000000 - 17fffffff 300000000
+";
+        assert!(is_text_mem_map(text));
+        assert_eq!(
+            ranges(&parse(text).unwrap()),
+            [
+                (0x1000, 0x9e000, 0x1000),
+                (0x100000, 0x7ff00000, 0x2_0000_0000),
+                (0x1_0000_0000, 0x8000_0000, 0x3_0000_0000)
+            ]
+        );
+    }
+
+    #[test]
+    fn parse_without_index() {
+        let text = "1000 - 9efff\n0x100000-0x7fffffff\n";
+        assert!(is_text_mem_map(text));
+        assert_eq!(
+            ranges(&parse(text).unwrap()),
+            [(0x1000, 0x9e000, 0x1000), (0x100000, 0x7ff00000, 0x100000)]
+        );
+    }
+
+    #[test]
+    fn skip_header_lines() {
+        // MemProcFS precedes the ranges with a commented header
+        let text = "\
+# MemProcFS physical memory map
+#  idx             base -              top
+0000             1000 -            9efff
+
+0001           100000 -         7fffffff
+";
+        assert!(is_text_mem_map(text));
+        assert_eq!(
+            ranges(&parse(text).unwrap()),
+            [(0x1000, 0x9e000, 0x1000), (0x100000, 0x7ff00000, 0x100000)]
+        );
+    }
+
+    #[test]
+    fn reject_invalid_lines() {
+        assert!(!is_text_mem_map("[[range]]\nbase=0x1000\nlength=0x1000\n"));
+        assert!(!is_text_mem_map("# only a header\n"));
+        assert!(parse("0000 1000 - 9efff -> 200000000 trailing\n").is_err());
+        assert!(parse("0000 9efff - 1000\n").is_err());
+    }
+}
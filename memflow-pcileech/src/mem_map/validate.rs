@@ -0,0 +1,119 @@
+//! Sanity checks for memory maps before they are put into use.
+use std::fmt;
+
+use log::warn;
+
+use memflow::prelude::v1::*;
+
+const PAGE_SIZE: u64 = 0x1000;
+
+/// A single range of the memory map as it appeared in its source.
+struct Entry<'a> {
+    /// Position of the entry in the source, e.g. the n-th `[[range]]` of a TOML file.
+    nr: usize,
+    mapping: &'a PhysicalMemoryMapping,
+    base: u64,
+    end: u64,
+    real_base: u64,
+    real_end: u64,
+}
+
+impl fmt::Display for Entry<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "range #{} (base={:#x}, length={:#x}",
+            self.nr + 1,
+            self.base,
+            self.end - self.base
+        )?;
+        if self.real_base != self.base {
+            write!(f, ", real_base={:#x}", self.real_base)?;
+        }
+        f.write_str(")")
+    }
+}
+
+/// Validates the mappings and returns the ones that should be used.
+///
+/// Overlapping ranges and ranges that exceed the address space are rejected.
+/// Empty ranges are skipped. Unaligned ranges, ranges above `addr_max` and ranges
+/// that share the same memory on the device only produce warnings.
+pub(crate) fn validate(
+    mappings: &[PhysicalMemoryMapping],
+    addr_max: Option<u64>,
+) -> Result<Vec<PhysicalMemoryMapping>> {
+    let mut entries = Vec::with_capacity(mappings.len());
+    for (nr, mapping) in mappings.iter().enumerate() {
+        let base = mapping.base.to_umem();
+        let real_base = mapping.real_base.to_umem();
+        let size = mapping.size;
+
+        let (end, real_end) = match (base.checked_add(size), real_base.checked_add(size)) {
+            (Some(end), Some(real_end)) => (end, real_end),
+            _ => {
+                return Err(
+                    Error(ErrorOrigin::Connector, ErrorKind::InvalidMemorySize).log_error(format!(
+                        "memory map range #{} (base={:#x}, length={:#x}) exceeds the address space",
+                        nr + 1,
+                        base,
+                        size
+                    )),
+                )
+            }
+        };
+        let entry = Entry {
+            nr,
+            mapping,
+            base,
+            end,
+            real_base,
+            real_end,
+        };
+
+        if size == 0 {
+            warn!("memory map {} has a length of zero and is skipped", entry);
+            continue;
+        }
+
+        if (base | real_base | size) & (PAGE_SIZE - 1) != 0 {
+            warn!(
+                "memory map {} is not page aligned, accesses to the partial pages might fail",
+                entry
+            );
+        }
+
+        match addr_max {
+            Some(addr_max) if real_end > addr_max => warn!(
+                "memory map {} exceeds the maximum address {:#x} of the leechcore device",
+                entry, addr_max
+            ),
+            _ => (),
+        }
+
+        entries.push(entry);
+    }
+
+    // ranges may appear in any order, overlaps are detected on the sorted list
+    entries.sort_by_key(|entry| entry.base);
+    for pair in entries.windows(2) {
+        if pair[0].end > pair[1].base {
+            return Err(Error(ErrorOrigin::Connector, ErrorKind::Configuration)
+                .log_error(format!("memory map {} overlaps with {}", pair[0], pair[1])));
+        }
+    }
+
+    // two ranges that are backed by the same memory on the device alias each other
+    entries.sort_by_key(|entry| entry.real_base);
+    for pair in entries.windows(2) {
+        if pair[0].real_end > pair[1].real_base {
+            warn!(
+                "memory map {} and {} are backed by the same device memory",
+                pair[0], pair[1]
+            );
+        }
+    }
+
+    entries.sort_by_key(|entry| entry.nr);
+    Ok(entries.iter().map(|entry| *entry.mapping).collect())
+}
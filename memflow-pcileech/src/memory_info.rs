@@ -0,0 +1,68 @@
+use memflow::prelude::v1::*;
+
+use leechcore_sys::*;
+
+use crate::handle::LcHandle;
+use crate::option::LcOption;
+
+/// Information about the target system that LeechCore already knows,
+/// e.g. from the header of a crash dump.
+///
+/// Addresses that are not known to LeechCore are `None`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct LcMemoryInfo {
+    /// The architecture of the target system.
+    pub arch: Option<ArchitectureIdent>,
+    /// The number of processors of the target system.
+    pub num_processors: u64,
+    /// The directory table base of the kernel.
+    pub dtb: Option<Address>,
+    /// The base address of the kernel image.
+    pub kernel_base: Option<Address>,
+    /// The address of `PsActiveProcessHead`.
+    pub ps_active_process_head: Option<Address>,
+    /// The address of `PsLoadedModuleList`.
+    pub ps_loaded_module_list: Option<Address>,
+    /// The address of `KdDebuggerDataBlock`.
+    pub kd_debugger_data_block: Option<Address>,
+}
+
+impl LcMemoryInfo {
+    /// Reads the memory info from the given handle.
+    ///
+    /// Returns `None` if LeechCore does not report `LC_OPT_MEMORYINFO_VALID`.
+    pub(crate) fn query(handle: &LcHandle) -> Result<Option<Self>> {
+        if handle.get_option(LC_OPT_MEMORYINFO_VALID).unwrap_or(0) == 0 {
+            return Ok(None);
+        }
+
+        let get = |option: LcOption| -> Result<u64> {
+            Ok(option.get(handle)?.as_u64().unwrap_or_default())
+        };
+        let address = |option: LcOption| -> Result<Option<Address>> {
+            Ok(Some(get(option)?)
+                .filter(|&addr| addr != 0)
+                .map(Address::from))
+        };
+
+        // the constants are generated by bindgen
+        #[allow(non_upper_case_globals)]
+        let arch = match get(LcOption::MemoryInfoArch)? as LC_ARCH_TP {
+            tdLC_ARCH_TP_LC_ARCH_X86 => Some(ArchitectureIdent::X86(32, false)),
+            tdLC_ARCH_TP_LC_ARCH_X86PAE => Some(ArchitectureIdent::X86(32, true)),
+            tdLC_ARCH_TP_LC_ARCH_X64 => Some(ArchitectureIdent::X86(64, false)),
+            tdLC_ARCH_TP_LC_ARCH_ARM64 => Some(ArchitectureIdent::AArch64(size::kb(4))),
+            _ => None,
+        };
+
+        Ok(Some(Self {
+            arch,
+            num_processors: get(LcOption::MemoryInfoOsNumProcessors)?,
+            dtb: address(LcOption::MemoryInfoOsDtb)?,
+            kernel_base: address(LcOption::MemoryInfoOsKernelBase)?,
+            ps_active_process_head: address(LcOption::MemoryInfoOsPsActiveProcessHead)?,
+            ps_loaded_module_list: address(LcOption::MemoryInfoOsPsLoadedModuleList)?,
+            kd_debugger_data_block: address(LcOption::MemoryInfoOsKdDebuggerDataBlock)?,
+        }))
+    }
+}
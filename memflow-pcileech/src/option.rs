@@ -0,0 +1,246 @@
+use memflow::prelude::v1::*;
+
+use leechcore_sys::*;
+
+use crate::handle::LcHandle;
+
+/// A version triple as reported by LeechCore.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct LcVersion {
+    pub major: u64,
+    pub minor: u64,
+    pub revision: u64,
+}
+
+/// The value of a LeechCore option.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LcOptionValue {
+    Bool(bool),
+    U64(u64),
+    Version(LcVersion),
+}
+
+impl LcOptionValue {
+    /// Returns the value as a bool or `None` if the value is not a bool.
+    pub fn as_bool(&self) -> Option<bool> {
+        match self {
+            LcOptionValue::Bool(value) => Some(*value),
+            _ => None,
+        }
+    }
+
+    /// Returns the value as a u64 or `None` if the value is not a u64.
+    pub fn as_u64(&self) -> Option<u64> {
+        match self {
+            LcOptionValue::U64(value) => Some(*value),
+            _ => None,
+        }
+    }
+
+    /// Returns the value as a version or `None` if the value is not a version.
+    pub fn as_version(&self) -> Option<LcVersion> {
+        match self {
+            LcOptionValue::Version(value) => Some(*value),
+            _ => None,
+        }
+    }
+}
+
+/// The type of the value of a LeechCore option.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LcOptionKind {
+    Bool,
+    U64,
+    Version,
+}
+
+/// Typed wrapper around the `LC_OPT_*` options of LeechCore.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LcOption {
+    CorePrintfEnable,
+    CoreVerbose,
+    CoreVerboseExtra,
+    CoreVerboseExtraTlp,
+    /// Combination of `LC_OPT_CORE_VERSION_MAJOR`, `_MINOR` and `_REVISION`.
+    CoreVersion,
+    CoreAddrMax,
+    /// Call count of the given `LC_STATISTICS_ID_*`.
+    CoreStatisticsCallCount(u32),
+    /// Call time of the given `LC_STATISTICS_ID_*`.
+    CoreStatisticsCallTime(u32),
+    CoreVolatile,
+    CoreReadonly,
+
+    MemoryInfoValid,
+    MemoryInfoFlag32Bit,
+    MemoryInfoFlagPae,
+    MemoryInfoArch,
+    MemoryInfoOsVersionMinor,
+    MemoryInfoOsVersionMajor,
+    MemoryInfoOsDtb,
+    MemoryInfoOsPfn,
+    MemoryInfoOsPsLoadedModuleList,
+    MemoryInfoOsPsActiveProcessHead,
+    MemoryInfoOsMachineImageTp,
+    MemoryInfoOsNumProcessors,
+    MemoryInfoOsSystemTime,
+    MemoryInfoOsUptime,
+    MemoryInfoOsKernelBase,
+    MemoryInfoOsKernelHint,
+    MemoryInfoOsKdDebuggerDataBlock,
+
+    FpgaProbeMaxPages,
+    FpgaMaxSizeRx,
+    FpgaMaxSizeTx,
+    FpgaDelayProbeRead,
+    FpgaDelayProbeWrite,
+    FpgaDelayWrite,
+    FpgaDelayRead,
+    FpgaRetryOnError,
+    FpgaDeviceId,
+    FpgaFpgaId,
+    /// Combination of `LC_OPT_FPGA_VERSION_MAJOR` and `_MINOR`, the revision is always 0.
+    FpgaVersion,
+    FpgaAlgoTiny,
+    FpgaAlgoSynchronous,
+    FpgaCfgSpaceXilinx,
+    FpgaTlpReadCbWithInfo,
+    FpgaTlpReadCbFilterCpl,
+}
+
+impl LcOption {
+    /// Returns the type of the value of this option.
+    pub fn kind(self) -> LcOptionKind {
+        match self {
+            LcOption::CorePrintfEnable
+            | LcOption::CoreVerbose
+            | LcOption::CoreVerboseExtra
+            | LcOption::CoreVerboseExtraTlp
+            | LcOption::CoreVolatile
+            | LcOption::CoreReadonly
+            | LcOption::MemoryInfoValid
+            | LcOption::MemoryInfoFlag32Bit
+            | LcOption::MemoryInfoFlagPae
+            | LcOption::FpgaRetryOnError
+            | LcOption::FpgaAlgoTiny
+            | LcOption::FpgaAlgoSynchronous
+            | LcOption::FpgaCfgSpaceXilinx
+            | LcOption::FpgaTlpReadCbWithInfo
+            | LcOption::FpgaTlpReadCbFilterCpl => LcOptionKind::Bool,
+            LcOption::CoreVersion | LcOption::FpgaVersion => LcOptionKind::Version,
+            _ => LcOptionKind::U64,
+        }
+    }
+
+    /// Returns the raw `LC_OPT_*` values backing this option.
+    fn raw(self) -> &'static [u64] {
+        match self {
+            LcOption::CorePrintfEnable => &[LC_OPT_CORE_PRINTF_ENABLE],
+            LcOption::CoreVerbose => &[LC_OPT_CORE_VERBOSE],
+            LcOption::CoreVerboseExtra => &[LC_OPT_CORE_VERBOSE_EXTRA],
+            LcOption::CoreVerboseExtraTlp => &[LC_OPT_CORE_VERBOSE_EXTRA_TLP],
+            LcOption::CoreVersion => &[
+                LC_OPT_CORE_VERSION_MAJOR,
+                LC_OPT_CORE_VERSION_MINOR,
+                LC_OPT_CORE_VERSION_REVISION,
+            ],
+            LcOption::CoreAddrMax => &[LC_OPT_CORE_ADDR_MAX],
+            LcOption::CoreStatisticsCallCount(_) => &[LC_OPT_CORE_STATISTICS_CALL_COUNT],
+            LcOption::CoreStatisticsCallTime(_) => &[LC_OPT_CORE_STATISTICS_CALL_TIME],
+            LcOption::CoreVolatile => &[LC_OPT_CORE_VOLATILE],
+            LcOption::CoreReadonly => &[LC_OPT_CORE_READONLY],
+
+            LcOption::MemoryInfoValid => &[LC_OPT_MEMORYINFO_VALID],
+            LcOption::MemoryInfoFlag32Bit => &[LC_OPT_MEMORYINFO_FLAG_32BIT],
+            LcOption::MemoryInfoFlagPae => &[LC_OPT_MEMORYINFO_FLAG_PAE],
+            LcOption::MemoryInfoArch => &[LC_OPT_MEMORYINFO_ARCH],
+            LcOption::MemoryInfoOsVersionMinor => &[LC_OPT_MEMORYINFO_OS_VERSION_MINOR],
+            LcOption::MemoryInfoOsVersionMajor => &[LC_OPT_MEMORYINFO_OS_VERSION_MAJOR],
+            LcOption::MemoryInfoOsDtb => &[LC_OPT_MEMORYINFO_OS_DTB],
+            LcOption::MemoryInfoOsPfn => &[LC_OPT_MEMORYINFO_OS_PFN],
+            LcOption::MemoryInfoOsPsLoadedModuleList => &[LC_OPT_MEMORYINFO_OS_PsLoadedModuleList],
+            LcOption::MemoryInfoOsPsActiveProcessHead => {
+                &[LC_OPT_MEMORYINFO_OS_PsActiveProcessHead]
+            }
+            LcOption::MemoryInfoOsMachineImageTp => &[LC_OPT_MEMORYINFO_OS_MACHINE_IMAGE_TP],
+            LcOption::MemoryInfoOsNumProcessors => &[LC_OPT_MEMORYINFO_OS_NUM_PROCESSORS],
+            LcOption::MemoryInfoOsSystemTime => &[LC_OPT_MEMORYINFO_OS_SYSTEMTIME],
+            LcOption::MemoryInfoOsUptime => &[LC_OPT_MEMORYINFO_OS_UPTIME],
+            LcOption::MemoryInfoOsKernelBase => &[LC_OPT_MEMORYINFO_OS_KERNELBASE],
+            LcOption::MemoryInfoOsKernelHint => &[LC_OPT_MEMORYINFO_OS_KERNELHINT],
+            LcOption::MemoryInfoOsKdDebuggerDataBlock => {
+                &[LC_OPT_MEMORYINFO_OS_KdDebuggerDataBlock]
+            }
+
+            LcOption::FpgaProbeMaxPages => &[LC_OPT_FPGA_PROBE_MAXPAGES],
+            LcOption::FpgaMaxSizeRx => &[LC_OPT_FPGA_MAX_SIZE_RX],
+            LcOption::FpgaMaxSizeTx => &[LC_OPT_FPGA_MAX_SIZE_TX],
+            LcOption::FpgaDelayProbeRead => &[LC_OPT_FPGA_DELAY_PROBE_READ],
+            LcOption::FpgaDelayProbeWrite => &[LC_OPT_FPGA_DELAY_PROBE_WRITE],
+            LcOption::FpgaDelayWrite => &[LC_OPT_FPGA_DELAY_WRITE],
+            LcOption::FpgaDelayRead => &[LC_OPT_FPGA_DELAY_READ],
+            LcOption::FpgaRetryOnError => &[LC_OPT_FPGA_RETRY_ON_ERROR],
+            LcOption::FpgaDeviceId => &[LC_OPT_FPGA_DEVICE_ID],
+            LcOption::FpgaFpgaId => &[LC_OPT_FPGA_FPGA_ID],
+            LcOption::FpgaVersion => &[LC_OPT_FPGA_VERSION_MAJOR, LC_OPT_FPGA_VERSION_MINOR],
+            LcOption::FpgaAlgoTiny => &[LC_OPT_FPGA_ALGO_TINY],
+            LcOption::FpgaAlgoSynchronous => &[LC_OPT_FPGA_ALGO_SYNCHRONOUS],
+            LcOption::FpgaCfgSpaceXilinx => &[LC_OPT_FPGA_CFGSPACE_XILINX],
+            LcOption::FpgaTlpReadCbWithInfo => &[LC_OPT_FPGA_TLP_READ_CB_WITHINFO],
+            LcOption::FpgaTlpReadCbFilterCpl => &[LC_OPT_FPGA_TLP_READ_CB_FILTERCPL],
+        }
+    }
+
+    /// Returns the statistics call id that is or'ed into the raw option.
+    fn call_id(self) -> u64 {
+        match self {
+            LcOption::CoreStatisticsCallCount(id) | LcOption::CoreStatisticsCallTime(id) => {
+                id as u64
+            }
+            _ => 0,
+        }
+    }
+
+    /// Reads this option from the given handle.
+    pub(crate) fn get(self, handle: &LcHandle) -> Result<LcOptionValue> {
+        let mut values = [0u64; 3];
+        for (value, &raw) in values.iter_mut().zip(self.raw().iter()) {
+            *value = handle.get_option(raw | self.call_id()).ok_or_else(|| {
+                Error(ErrorOrigin::Connector, ErrorKind::NotSupported)
+                    .log_error(format!("unable to get leechcore option {self:?}"))
+            })?;
+        }
+
+        Ok(match self.kind() {
+            LcOptionKind::Bool => LcOptionValue::Bool(values[0] != 0),
+            LcOptionKind::U64 => LcOptionValue::U64(values[0]),
+            LcOptionKind::Version => LcOptionValue::Version(LcVersion {
+                major: values[0],
+                minor: values[1],
+                revision: values[2],
+            }),
+        })
+    }
+
+    /// Writes this option to the given handle.
+    pub(crate) fn set(self, handle: &LcHandle, value: LcOptionValue) -> Result<()> {
+        let raw = match (self.kind(), value) {
+            (LcOptionKind::Bool, LcOptionValue::Bool(value)) => value as u64,
+            (LcOptionKind::U64, LcOptionValue::U64(value)) => value,
+            _ => {
+                return Err(
+                    Error(ErrorOrigin::Connector, ErrorKind::InvalidArgument).log_error(format!(
+                        "invalid value {value:?} for leechcore option {self:?}"
+                    )),
+                )
+            }
+        };
+
+        if handle.set_option(self.raw()[0] | self.call_id(), raw) {
+            Ok(())
+        } else {
+            Err(Error(ErrorOrigin::Connector, ErrorKind::NotSupported)
+                .log_error(format!("unable to set leechcore option {self:?}")))
+        }
+    }
+}
@@ -0,0 +1,182 @@
+use std::cell::RefCell;
+use std::ffi::CStr;
+use std::os::raw::{c_char, c_int};
+
+use log::Level;
+use parking_lot::Mutex;
+
+use leechcore_sys::*;
+
+/// The log target used for all messages printed by LeechCore.
+const LOG_TARGET: &str = "leechcore";
+
+type PrintfFn = unsafe extern "C" fn(*const c_char, ...) -> c_int;
+
+// the level verbose messages are logged with, `None` if verbose output is disabled
+static VERBOSE_LEVEL: Mutex<Option<Level>> = Mutex::new(None);
+
+// buffers partial lines until they are terminated
+static LINE_BUFFER: Mutex<String> = Mutex::new(String::new());
+
+thread_local! {
+    // collects the lines printed on this thread while `capture` is running
+    static CAPTURED: RefCell<Option<Vec<String>>> = const { RefCell::new(None) };
+}
+
+/// Returns the `LC_CONFIG_PRINTF_*` verbosity matching the log levels enabled for the `leechcore` target.
+///
+/// This way filtering the target, e.g. via `RUST_LOG=info,leechcore=warn`, also keeps LeechCore
+/// from printing verbose output in the first place.
+/// Regular output is always enabled so the reason for failures can be captured via [`capture`].
+pub(crate) fn verbosity() -> u32 {
+    let enabled = |level: Level| log::log_enabled!(target: LOG_TARGET, level);
+    if enabled(Level::Trace) {
+        LC_CONFIG_PRINTF_ENABLED | LC_CONFIG_PRINTF_V | LC_CONFIG_PRINTF_VV | LC_CONFIG_PRINTF_VVV
+    } else if enabled(Level::Debug) {
+        LC_CONFIG_PRINTF_ENABLED | LC_CONFIG_PRINTF_V | LC_CONFIG_PRINTF_VV
+    } else if enabled(Level::Info) {
+        LC_CONFIG_PRINTF_ENABLED | LC_CONFIG_PRINTF_V
+    } else {
+        LC_CONFIG_PRINTF_ENABLED
+    }
+}
+
+/// Sets up the LeechCore printf forwarding for the given `LC_CONFIG_PRINTF_*` verbosity.
+///
+/// Returns the function that has to be set as `pfn_printf_opt` in `LC_CONFIG`.
+pub(crate) fn init(printf_verbosity: u32) -> Option<PrintfFn> {
+    if printf_verbosity & LC_CONFIG_PRINTF_ENABLED == 0 {
+        return None;
+    }
+
+    // leechcore does not tell which verbosity a message was printed with,
+    // with verbose output enabled all messages except errors are logged with the most verbose level
+    let verbose_level = if printf_verbosity & LC_CONFIG_PRINTF_VVV != 0 {
+        Some(Level::Trace)
+    } else if printf_verbosity & LC_CONFIG_PRINTF_VV != 0 {
+        Some(Level::Debug)
+    } else if printf_verbosity & LC_CONFIG_PRINTF_V != 0 {
+        Some(Level::Info)
+    } else {
+        None
+    };
+    *VERBOSE_LEVEL.lock() = verbose_level;
+
+    unsafe { leechcore_set_printf_callback(Some(printf_callback)) };
+    Some(leechcore_printf)
+}
+
+/// Logs any partial line that is still buffered.
+///
+/// LeechCore does not always terminate its last message with a newline.
+fn flush() {
+    let line = std::mem::take(&mut *LINE_BUFFER.lock());
+    log_line(&line);
+}
+
+/// Runs `f` and returns all lines LeechCore printed on the current thread in the meantime.
+///
+/// The lines are logged as usual, capturing them allows to report them in errors as well.
+pub(crate) fn capture<R>(f: impl FnOnce() -> R) -> (R, Vec<String>) {
+    CAPTURED.with(|captured| *captured.borrow_mut() = Some(Vec::new()));
+    let result = f();
+    flush();
+    let lines = CAPTURED.with(|captured| captured.borrow_mut().take());
+    (result, lines.unwrap_or_default())
+}
+
+/// Returns the level a single line of LeechCore output is logged with.
+///
+/// `verbose` is the level verbose output is logged with, `None` if verbose output is disabled.
+fn level(line: &str, verbose: Option<Level>) -> Level {
+    // errors are printed regardless of the verbosity
+    let upper = line.to_ascii_uppercase();
+    if ["ERROR", "FAIL", "WARN"]
+        .iter()
+        .any(|keyword| upper.contains(keyword))
+    {
+        return Level::Warn;
+    }
+
+    // without verbose output all messages are regular ones
+    verbose.unwrap_or(Level::Warn)
+}
+
+/// Appends `msg` to the buffer and returns all lines that are complete now.
+///
+/// The returned lines still contain their trailing newline.
+fn take_lines(buffer: &mut String, msg: &str) -> Vec<String> {
+    buffer.push_str(msg);
+    let mut lines = Vec::new();
+    while let Some(pos) = buffer.find('\n') {
+        lines.push(buffer.drain(..=pos).collect());
+    }
+    lines
+}
+
+fn log_line(line: &str) {
+    let line = line.trim_end();
+    if !line.is_empty() {
+        let level = level(line, *VERBOSE_LEVEL.lock());
+        log::log!(target: LOG_TARGET, level, "{}", line);
+        CAPTURED.with(|captured| {
+            if let Some(lines) = captured.borrow_mut().as_mut() {
+                lines.push(line.to_string());
+            }
+        });
+    }
+}
+
+unsafe extern "C" fn printf_callback(msg: *const c_char) {
+    if msg.is_null() {
+        return;
+    }
+    let msg = CStr::from_ptr(msg).to_string_lossy();
+
+    let lines = take_lines(&mut LINE_BUFFER.lock(), &msg);
+    for line in lines {
+        log_line(&line);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn lines_are_split_at_newlines() {
+        let mut buffer = String::new();
+        assert!(take_lines(&mut buffer, "DEVICE: ").is_empty());
+        assert_eq!(
+            take_lines(&mut buffer, "FPGA\nFPGA: 0x"),
+            vec!["DEVICE: FPGA\n".to_string()]
+        );
+        assert_eq!(
+            take_lines(&mut buffer, "10\n\nDONE\n"),
+            vec![
+                "FPGA: 0x10\n".to_string(),
+                "\n".to_string(),
+                "DONE\n".to_string()
+            ]
+        );
+        assert!(buffer.is_empty());
+    }
+
+    #[test]
+    fn errors_are_logged_as_warnings() {
+        for verbose in [None, Some(Level::Info), Some(Level::Trace)] {
+            assert_eq!(level("ERROR: unable to open device", verbose), Level::Warn);
+            assert_eq!(level("Device Init Failed", verbose), Level::Warn);
+            assert_eq!(level("warning: slow link", verbose), Level::Warn);
+        }
+    }
+
+    #[test]
+    fn regular_messages_use_the_verbose_level() {
+        let line = "DEVICE: FPGA: PCIe gen2 x1";
+        assert_eq!(level(line, None), Level::Warn);
+        assert_eq!(level(line, Some(Level::Info)), Level::Info);
+        assert_eq!(level(line, Some(Level::Debug)), Level::Debug);
+        assert_eq!(level(line, Some(Level::Trace)), Level::Trace);
+    }
+}
@@ -0,0 +1,504 @@
+use std::ffi::c_void;
+use std::ptr;
+
+use memflow::prelude::v1::*;
+
+use leechcore_sys::*;
+
+use crate::handle::LcHandle;
+use crate::PAGE_SIZE;
+
+// the absolute minimum BUF_ALIGN is 4.
+// using 8 bytes as BUF_ALIGN here simplifies things a lot
+// and makes our gap detection code work in cases where page boundaries would be crossed.
+const BUF_ALIGN: u64 = 8;
+const BUF_MIN_LEN: usize = 8;
+const BUF_LEN_ALIGN: usize = 8;
+
+/// The maximum number of pages in a single scatter call.
+///
+/// The size of the scatter buffer is passed to LeechCore as a `u32`.
+pub(crate) const MAX_BATCH_PAGES: usize = u32::MAX as usize / PAGE_SIZE;
+
+/// A part of a memory operation that does not cross a page boundary.
+pub(crate) struct PageChunk<T> {
+    /// The address on the device.
+    pub addr: Address,
+    /// The address reported back to memflow.
+    pub meta_addr: Address,
+    pub buf: T,
+}
+
+/// Owned array of `MEM_SCATTER` entries.
+///
+/// The entries are freed via `LcMemFree` when this object is dropped.
+struct ScatterArray {
+    mems: PPMEM_SCATTER,
+    len: usize,
+}
+
+impl Default for ScatterArray {
+    fn default() -> Self {
+        Self {
+            mems: ptr::null_mut(),
+            len: 0,
+        }
+    }
+}
+
+impl ScatterArray {
+    fn alloc(len: usize) -> Result<Self> {
+        if len > MAX_BATCH_PAGES {
+            return Err(Error(ErrorOrigin::Connector, ErrorKind::InvalidMemorySize)
+                .log_error(format!("scatter batch of {len} pages is too large")));
+        }
+
+        let mut mems = ptr::null_mut::<PMEM_SCATTER>();
+        let result = unsafe {
+            LcAllocScatter2(
+                (len * PAGE_SIZE) as u32,
+                ptr::null_mut(),
+                len as u32,
+                &mut mems as *mut PPMEM_SCATTER,
+            )
+        };
+        if result != 1 {
+            return Err(Error(ErrorOrigin::Connector, ErrorKind::InvalidMemorySize)
+                .log_error("unable to allocate scatter buffer"));
+        }
+        Ok(Self { mems, len })
+    }
+
+    fn get(&self, idx: usize) -> PMEM_SCATTER {
+        assert!(idx < self.len);
+        unsafe { *self.mems.add(idx) }
+    }
+
+    /// Points the entry at the given buffer and resets its result.
+    ///
+    /// The buffer has to outlive all scatter calls using this entry.
+    fn set(&self, idx: usize, addr: u64, buf: *mut u8, len: usize) {
+        let mem = self.get(idx);
+        unsafe {
+            (*mem).qwA = addr;
+            (*mem).__bindgen_anon_1.pb = buf;
+            (*mem).cb = len as u32;
+            (*mem).f = 0;
+        }
+    }
+
+    fn success(&self, idx: usize) -> bool {
+        unsafe { (*self.get(idx)).f != 0 }
+    }
+}
+
+impl Drop for ScatterArray {
+    fn drop(&mut self) {
+        if !self.mems.is_null() {
+            unsafe { LcMemFree(self.mems as *mut c_void) };
+        }
+    }
+}
+
+/// Temporary buffer for chunks that do not fulfill the alignment requirements of LeechCore.
+struct Gap {
+    idx: usize,
+    addr: u64,
+    /// Offset of the buffer in the gap data of the pool.
+    offset: usize,
+    len: usize,
+    /// Offset of the chunk in the buffer.
+    start: usize,
+}
+
+impl Gap {
+    fn needed(addr: Address, len: usize) -> bool {
+        addr.to_umem() & (BUF_ALIGN - 1) != 0 || len & (BUF_LEN_ALIGN - 1) != 0 || len < BUF_MIN_LEN
+    }
+
+    fn new(idx: usize, addr: Address, len: usize, offset: usize) -> Self {
+        let addr_align = addr.to_umem() & (BUF_ALIGN - 1);
+        let addr_aligned = addr.to_umem() - addr_align;
+        let mut buffer_len = len + addr_align as usize;
+        let buf_align = buffer_len & (BUF_LEN_ALIGN - 1);
+        if buf_align > 0 {
+            buffer_len += BUF_LEN_ALIGN - buf_align;
+        }
+        buffer_len = buffer_len.max(BUF_MIN_LEN);
+
+        // note that this always holds true because addr alignment is equal to buf length alignment
+        assert!(buffer_len >= len);
+
+        // we never want to cross page boundaries, otherwise the access will just not work
+        assert_eq!(
+            addr.to_umem() - (addr.to_umem() & (PAGE_SIZE as umem - 1)),
+            (addr_aligned + buffer_len as umem - 1)
+                - ((addr_aligned + buffer_len as umem - 1) & (PAGE_SIZE as umem - 1))
+        );
+
+        Self {
+            idx,
+            addr: addr_aligned,
+            offset,
+            len: buffer_len,
+            start: addr_align as usize,
+        }
+    }
+
+    /// Creates a buffer covering the entire page at `page_addr`.
+    fn page(idx: usize, page_addr: u64, offset: usize) -> Self {
+        Self::window(idx, page_addr, PAGE_SIZE, offset)
+    }
+
+    /// Creates a buffer covering an already aligned window of memory.
+    fn window(idx: usize, addr: u64, len: usize, offset: usize) -> Self {
+        Self {
+            idx,
+            addr,
+            offset,
+            len,
+            start: 0,
+        }
+    }
+}
+
+/// Reads a contiguous block via a single `LcRead` call.
+///
+/// Returns false if any part of the block could not be read.
+pub(crate) fn read_contiguous(handle: &LcHandle, addr: Address, buf: &mut [u8]) -> bool {
+    unsafe {
+        LcRead(
+            handle.as_raw(),
+            addr.to_umem(),
+            buf.len() as u32,
+            buf.as_mut_ptr(),
+        ) != 0
+    }
+}
+
+/// Writes a contiguous block via a single `LcWrite` call.
+///
+/// Returns false if any part of the block could not be written.
+pub(crate) fn write_contiguous(handle: &LcHandle, addr: Address, buf: &[u8]) -> bool {
+    unsafe {
+        LcWrite(
+            handle.as_raw(),
+            addr.to_umem(),
+            buf.len() as u32,
+            buf.as_ptr() as *mut u8,
+        ) != 0
+    }
+}
+
+/// Returns the `[start, end)` window around the chunk that fulfills the alignment requirements of LeechCore.
+fn aligned_window(addr: Address, len: usize) -> (u64, u64) {
+    let start = addr.to_umem() & !(BUF_ALIGN - 1);
+    let end =
+        (addr.to_umem() + len as umem + BUF_LEN_ALIGN as umem - 1) & !(BUF_LEN_ALIGN as umem - 1);
+    (start, end.max(start + BUF_MIN_LEN as umem))
+}
+
+/// Returns the address of the page containing `addr`.
+fn page_addr(addr: Address) -> u64 {
+    addr.to_umem() & !(PAGE_SIZE as umem - 1)
+}
+
+/// Scatter entries and gap buffers that are reused between calls.
+///
+/// Allocating them for every read and write shows up clearly at high request rates.
+/// The scatter array only grows and is kept at the size of the largest batch seen so far.
+#[derive(Default)]
+pub(crate) struct ScatterPool {
+    mems: ScatterArray,
+    gaps: Vec<Gap>,
+    /// Backing memory of all gap buffers of the current batch.
+    gap_data: Vec<u8>,
+    /// The entries handed to LeechCore when only a subset of the batch is dispatched.
+    subset: Vec<PMEM_SCATTER>,
+    skip: Vec<bool>,
+    /// The chunk indices of the current batch sorted by their address.
+    order: Vec<usize>,
+    /// The scatter entry and gap data location of every chunk of the current batch.
+    targets: Vec<Target>,
+}
+
+/// Describes which scatter entry a chunk is read or written with.
+#[derive(Clone, Copy, Default)]
+struct Target {
+    /// Index of the scatter entry of the chunk.
+    entry: usize,
+    /// Offset of the chunk in the gap data, `None` if the entry points directly at the chunk.
+    gap_offset: Option<usize>,
+}
+
+// the raw pointers only reference memory that is owned by the pool itself
+// and the pool is only used while the leechcore handle is locked.
+unsafe impl Send for ScatterPool {}
+
+impl ScatterPool {
+    /// Makes sure the scatter array holds at least `len` entries.
+    fn reserve(&mut self, len: usize) -> Result<()> {
+        if self.mems.len < len {
+            // free the old array before allocating the new one
+            self.mems = ScatterArray::default();
+            self.mems = ScatterArray::alloc(len)?;
+        }
+        Ok(())
+    }
+
+    /// Allocates the gap data and points the scatter entries of all gaps at their buffers.
+    fn setup_gaps(&mut self, gap_data_len: usize) {
+        // the gap data is not resized anymore until the next call so the pointers stay valid
+        self.gap_data.clear();
+        self.gap_data.resize(gap_data_len, 0);
+        for gap in self.gaps.iter() {
+            let buf = unsafe { self.gap_data.as_mut_ptr().add(gap.offset) };
+            self.mems.set(gap.idx, gap.addr, buf, gap.len);
+        }
+    }
+
+    /// Sets up the scatter entries for reading the given chunks and returns the number of entries.
+    ///
+    /// Chunks that overlap or share a page with other chunks are coalesced into a single read of the entire page.
+    /// OS layers tend to issue lots of small reads into the same page (e.g. when walking page tables or lists),
+    /// reading the page once saves a round trip to the device for every one of them.
+    fn plan_read(&mut self, chunks: &mut [PageChunk<CSliceMut<u8>>]) -> Result<usize> {
+        self.reserve(chunks.len())?;
+
+        self.order.clear();
+        self.order.extend(0..chunks.len());
+        self.order.sort_unstable_by_key(|&idx| chunks[idx].addr);
+
+        self.gaps.clear();
+        self.targets.clear();
+        self.targets.resize(chunks.len(), Target::default());
+
+        let mut entries = 0;
+        let mut gap_data_len = 0;
+        let mut pos = 0;
+        while pos < self.order.len() {
+            // chunks never cross a page boundary, chunks on the same page are therefore the only ones that can overlap
+            let page = page_addr(chunks[self.order[pos]].addr);
+            let group_len = self.order[pos..]
+                .iter()
+                .take_while(|&&idx| page_addr(chunks[idx].addr) == page)
+                .count();
+            let group = &self.order[pos..pos + group_len];
+
+            if let [idx] = *group {
+                let chunk = &mut chunks[idx];
+                if Gap::needed(chunk.addr, chunk.buf.len()) {
+                    // non-aligned or small read
+                    let gap = Gap::new(entries, chunk.addr, chunk.buf.len(), gap_data_len);
+                    self.targets[idx] = Target {
+                        entry: entries,
+                        gap_offset: Some(gap.offset + gap.start),
+                    };
+                    gap_data_len += gap.len;
+                    self.gaps.push(gap);
+                } else {
+                    // properly aligned read
+                    self.mems.set(
+                        entries,
+                        chunk.addr.to_umem(),
+                        chunk.buf.as_mut_ptr(),
+                        chunk.buf.len(),
+                    );
+                    self.targets[idx] = Target {
+                        entry: entries,
+                        gap_offset: None,
+                    };
+                }
+            } else {
+                // multiple reads on the same page
+                let gap = Gap::page(entries, page, gap_data_len);
+                for &idx in group {
+                    self.targets[idx] = Target {
+                        entry: entries,
+                        gap_offset: Some(gap.offset + (chunks[idx].addr.to_umem() - page) as usize),
+                    };
+                }
+                gap_data_len += gap.len;
+                self.gaps.push(gap);
+            }
+
+            entries += 1;
+            pos += group_len;
+        }
+
+        self.setup_gaps(gap_data_len);
+        Ok(entries)
+    }
+
+    /// Sets up the scatter entries for writing the given chunks and returns the number of entries.
+    ///
+    /// Chunks that are not aligned are written via a buffer covering the surrounding aligned window.
+    /// Chunks whose windows overlap share a single buffer,
+    /// otherwise each of them would write back the original contents of its neighbours.
+    fn plan_write(&mut self, chunks: &[PageChunk<CSliceRef<u8>>]) -> Result<usize> {
+        self.reserve(chunks.len())?;
+
+        self.order.clear();
+        self.order.extend(0..chunks.len());
+        self.order.sort_unstable_by_key(|&idx| chunks[idx].addr);
+
+        self.gaps.clear();
+        self.targets.clear();
+        self.targets.resize(chunks.len(), Target::default());
+
+        let mut entries = 0;
+        let mut gap_data_len = 0;
+        let mut pos = 0;
+        while pos < self.order.len() {
+            // collect all chunks with overlapping windows,
+            // windows never cross a page boundary because pages are aligned as well
+            let first = &chunks[self.order[pos]];
+            let (start, mut end) = aligned_window(first.addr, first.buf.len());
+            let mut group_len = 1;
+            while let Some(&idx) = self.order.get(pos + group_len) {
+                let (next_start, next_end) =
+                    aligned_window(chunks[idx].addr, chunks[idx].buf.len());
+                if next_start >= end {
+                    break;
+                }
+                end = end.max(next_end);
+                group_len += 1;
+            }
+            let group = &self.order[pos..pos + group_len];
+
+            if group_len == 1 && !Gap::needed(first.addr, first.buf.len()) {
+                // properly aligned write
+                self.mems.set(
+                    entries,
+                    first.addr.to_umem(),
+                    first.buf.as_ptr() as *mut u8,
+                    first.buf.len(),
+                );
+                self.targets[group[0]] = Target {
+                    entry: entries,
+                    gap_offset: None,
+                };
+            } else {
+                // non-aligned, small or overlapping writes
+                let gap = Gap::window(entries, start, (end - start) as usize, gap_data_len);
+                for &idx in group {
+                    self.targets[idx] = Target {
+                        entry: entries,
+                        gap_offset: Some(
+                            gap.offset + (chunks[idx].addr.to_umem() - start) as usize,
+                        ),
+                    };
+                }
+                gap_data_len += gap.len;
+                self.gaps.push(gap);
+            }
+
+            entries += 1;
+            pos += group_len;
+        }
+
+        self.setup_gaps(gap_data_len);
+        Ok(entries)
+    }
+
+    /// Reads all chunks via a single `LcReadScatter` call.
+    ///
+    /// The chunks are coalesced as described in [`Self::plan_read`].
+    /// `success` is filled with the result of every chunk.
+    pub(crate) fn read(
+        &mut self,
+        handle: &LcHandle,
+        chunks: &mut [PageChunk<CSliceMut<u8>>],
+        success: &mut Vec<bool>,
+    ) -> Result<()> {
+        let entries = self.plan_read(chunks)?;
+
+        unsafe { LcReadScatter(handle.as_raw(), entries as u32, self.mems.mems) };
+
+        // check which reads actually succeeded
+        let mems = &self.mems;
+        success.clear();
+        success.extend(self.targets.iter().map(|target| mems.success(target.entry)));
+
+        // only copy data that was actually read
+        for (idx, target) in self.targets.iter().enumerate() {
+            if let (Some(offset), true) = (target.gap_offset, success[idx]) {
+                let buf = &mut chunks[idx].buf;
+                let len = buf.len();
+                buf.copy_from_slice(&self.gap_data[offset..offset + len]);
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Writes all chunks via a single `LcWriteScatter` call.
+    ///
+    /// Chunks that are not aligned are merged into the surrounding memory first.
+    /// Overlapping chunks are written in order, later chunks take precedence over earlier ones.
+    /// The handle has to stay locked for the entire read-modify-write cycle.
+    /// `success` is filled with the result of every chunk.
+    pub(crate) fn write(
+        &mut self,
+        handle: &LcHandle,
+        chunks: &[PageChunk<CSliceRef<u8>>],
+        success: &mut Vec<bool>,
+    ) -> Result<()> {
+        let entries = self.plan_write(chunks)?;
+
+        // read the memory surrounding unaligned writes so it is written back unchanged
+        let mems = &self.mems;
+        self.skip.clear();
+        self.skip.resize(entries, false);
+        if !self.gaps.is_empty() {
+            self.subset.clear();
+            self.subset
+                .extend(self.gaps.iter().map(|gap| mems.get(gap.idx)));
+            unsafe {
+                LcReadScatter(
+                    handle.as_raw(),
+                    self.subset.len() as u32,
+                    self.subset.as_mut_ptr(),
+                )
+            };
+
+            for gap in self.gaps.iter() {
+                if mems.success(gap.idx) {
+                    unsafe { (*mems.get(gap.idx)).f = 0 };
+                } else {
+                    // never write back memory we could not read
+                    self.skip[gap.idx] = true;
+                }
+            }
+
+            // merge the user data into the existing memory
+            for (chunk, target) in chunks.iter().zip(self.targets.iter()) {
+                if let Some(offset) = target.gap_offset.filter(|_| !self.skip[target.entry]) {
+                    self.gap_data[offset..offset + chunk.buf.len()].copy_from_slice(&chunk.buf);
+                }
+            }
+        }
+
+        // dispatch write
+        let skip = &self.skip;
+        self.subset.clear();
+        self.subset.extend(
+            (0..entries)
+                .filter(|&entry| !skip[entry])
+                .map(|entry| mems.get(entry)),
+        );
+        unsafe {
+            LcWriteScatter(
+                handle.as_raw(),
+                self.subset.len() as u32,
+                self.subset.as_mut_ptr(),
+            )
+        };
+
+        // check which writes actually succeeded
+        success.clear();
+        success.extend(self.targets.iter().map(|target| mems.success(target.entry)));
+
+        Ok(())
+    }
+}
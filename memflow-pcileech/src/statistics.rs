@@ -0,0 +1,100 @@
+use std::mem::size_of;
+use std::ptr;
+use std::time::Duration;
+
+use memflow::prelude::v1::*;
+
+use leechcore_sys::*;
+
+use crate::handle::LcHandle;
+
+/// Number of calls and the total time spent in a single LeechCore function.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct LcCallStatistics {
+    pub count: u64,
+    pub time: Duration,
+}
+
+/// Snapshot of the LeechCore call statistics.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct LcStatistics {
+    pub open: LcCallStatistics,
+    pub read: LcCallStatistics,
+    pub read_scatter: LcCallStatistics,
+    pub write: LcCallStatistics,
+    pub write_scatter: LcCallStatistics,
+    pub get_option: LcCallStatistics,
+    pub set_option: LcCallStatistics,
+    pub command: LcCallStatistics,
+}
+
+impl LcStatistics {
+    /// Converts the raw statistics into a snapshot relative to the given baseline.
+    pub(crate) fn from_raw(raw: &LC_STATISTICS, baseline: Option<&LC_STATISTICS>) -> Self {
+        let call = |id: u32| {
+            let current = raw.Call[id as usize];
+            let (count, ticks) = match baseline {
+                Some(baseline) => {
+                    let baseline = baseline.Call[id as usize];
+                    (
+                        current.c.saturating_sub(baseline.c),
+                        current.tm.saturating_sub(baseline.tm),
+                    )
+                }
+                None => (current.c, current.tm),
+            };
+
+            // the call time is measured in ticks of qwFreq per second
+            let time = if raw.qwFreq != 0 {
+                Duration::from_nanos(
+                    (ticks as u128 * 1_000_000_000 / raw.qwFreq as u128).min(u64::MAX as u128)
+                        as u64,
+                )
+            } else {
+                Duration::default()
+            };
+
+            LcCallStatistics { count, time }
+        };
+
+        Self {
+            open: call(LC_STATISTICS_ID_OPEN),
+            read: call(LC_STATISTICS_ID_READ),
+            read_scatter: call(LC_STATISTICS_ID_READSCATTER),
+            write: call(LC_STATISTICS_ID_WRITE),
+            write_scatter: call(LC_STATISTICS_ID_WRITESCATTER),
+            get_option: call(LC_STATISTICS_ID_GETOPTION),
+            set_option: call(LC_STATISTICS_ID_SETOPTION),
+            command: call(LC_STATISTICS_ID_COMMAND),
+        }
+    }
+}
+
+/// Counters of the pages that were re-issued after they failed to be read or written.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct RetryStatistics {
+    /// Number of times a failed page was re-issued.
+    pub retried_pages: u64,
+    /// Number of pages that succeeded after at least one retry.
+    pub recovered_pages: u64,
+    /// Number of pages that were reported as failed after all retries were exhausted.
+    pub failed_pages: u64,
+}
+
+/// Retrieves the raw statistics via `LC_CMD_STATISTICS_GET`.
+pub(crate) fn raw_statistics(handle: &LcHandle) -> Result<LC_STATISTICS> {
+    let data = handle
+        .command(LC_CMD_STATISTICS_GET, &[])
+        .filter(|data| data.len() >= size_of::<LC_STATISTICS>())
+        .ok_or_else(|| {
+            Error(ErrorOrigin::Connector, ErrorKind::NotSupported)
+                .log_error("unable to retrieve leechcore statistics")
+        })?;
+
+    let raw = unsafe { ptr::read_unaligned(data.as_ptr() as *const LC_STATISTICS) };
+    if raw.dwVersion != LC_STATISTICS_VERSION {
+        return Err(Error(ErrorOrigin::Connector, ErrorKind::VersionMismatch)
+            .log_error("leechcore statistics version mismatch"));
+    }
+    Ok(raw)
+}
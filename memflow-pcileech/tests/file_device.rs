@@ -0,0 +1,666 @@
+/*!
+Tests the connector against the LeechCore `file` device backed by a temporary memory dump.
+*/
+use std::path::PathBuf;
+use std::time::Duration;
+
+use memflow::prelude::v1::*;
+use memflow_pcileech::{
+    LcCreateError, LcHandle, LcOption, LcOptionValue, LcVersion, MemMapPolicy, PciLeech,
+};
+
+const DUMP_SIZE: usize = 0x10000;
+const PAGE_SIZE: usize = 0x1000;
+
+/// Creates a dump file filled with a known pattern and returns its path.
+fn create_dump(name: &str) -> PathBuf {
+    let path = std::env::temp_dir().join(format!("memflow-pcileech-{name}.raw"));
+    let data = (0..DUMP_SIZE).map(|i| (i % 251) as u8).collect::<Vec<_>>();
+    std::fs::write(&path, data).expect("unable to create dump file");
+    path
+}
+
+#[test]
+fn report_failed_reads() {
+    let path = create_dump("read-fail");
+    let device = format!("file://{}", path.display());
+    let mut conn = PciLeech::new(&device, None, false).expect("unable to open file device");
+
+    // the second range ends beyond the end of the dump
+    conn.set_mem_map(&[
+        PhysicalMemoryMapping {
+            base: Address::NULL,
+            size: 0x2000,
+            real_base: Address::NULL,
+        },
+        PhysicalMemoryMapping {
+            base: Address::from(0x2000),
+            size: 0x2000,
+            real_base: Address::from(DUMP_SIZE - PAGE_SIZE),
+        },
+    ]);
+
+    // the read is split by the memory map, only its last page is beyond the end of the dump
+    let mut buf = vec![0u8; PAGE_SIZE * 3];
+    let mut read = Vec::new();
+    let mut failed = Vec::new();
+    MemOps::with(
+        std::iter::once((PhysicalAddress::from(0x1000), CSliceMut::from(&mut buf[..]))),
+        Some(
+            &mut (&mut |CTup2(addr, data): ReadData| {
+                read.push((addr, data.len()));
+                true
+            })
+                .into(),
+        ),
+        Some(
+            &mut (&mut |CTup2(addr, data): ReadData| {
+                failed.push((addr, data.len()));
+                true
+            })
+                .into(),
+        ),
+        |data| conn.phys_read_raw_iter(data),
+    )
+    .expect("failed reads are reported via out_fail");
+
+    // chunks are reported with their address before the memory map was applied
+    read.sort_unstable();
+    assert_eq!(
+        read,
+        [
+            (Address::from(0x1000), PAGE_SIZE),
+            (Address::from(0x2000), PAGE_SIZE),
+        ]
+    );
+    assert_eq!(failed, [(Address::from(0x3000), PAGE_SIZE)]);
+
+    let expected = (0x1000..0x2000)
+        .chain(DUMP_SIZE - PAGE_SIZE..DUMP_SIZE)
+        .map(|i| (i % 251) as u8)
+        .collect::<Vec<_>>();
+    assert_eq!(&buf[..PAGE_SIZE * 2], &expected[..]);
+}
+
+#[test]
+fn reject_writes_without_write_access() {
+    let path = create_dump("read-only");
+    let device = format!("file://{}", path.display());
+    let mut conn = PciLeech::new(&device, None, false).expect("unable to open file device");
+
+    let value = vec![0xffu8; PAGE_SIZE * 3];
+    assert!(conn
+        .phys_view()
+        .write_raw(Address::from(0x1003), &value[..5])
+        .is_err());
+
+    // unaligned, small and multi page writes are all reported with their original address
+    let writes = [(0x1003u64, 5usize), (0x2000, 8), (0x4000, PAGE_SIZE * 3)];
+    let mut failed = Vec::new();
+    MemOps::with(
+        writes
+            .iter()
+            .map(|&(addr, len)| (PhysicalAddress::from(addr), CSliceRef::from(&value[..len]))),
+        None,
+        Some(
+            &mut (&mut |CTup2(addr, data): WriteData| {
+                failed.push((addr, data.len()));
+                true
+            })
+                .into(),
+        ),
+        |data| conn.phys_write_raw_iter(data),
+    )
+    .expect("failed writes are reported via out_fail");
+
+    failed.sort_unstable();
+    assert_eq!(
+        failed,
+        [
+            (Address::from(0x1003), 5),
+            (Address::from(0x2000), 8),
+            (Address::from(0x4000), PAGE_SIZE),
+            (Address::from(0x5000), PAGE_SIZE),
+            (Address::from(0x6000), PAGE_SIZE),
+        ]
+    );
+
+    // the dump itself is left untouched
+    let mut buf = vec![0u8; DUMP_SIZE];
+    conn.phys_view()
+        .read_raw_into(Address::NULL, &mut buf)
+        .expect("unable to read physical memory");
+    assert!(buf.iter().enumerate().all(|(i, &b)| b == (i % 251) as u8));
+}
+
+#[test]
+fn typed_options() {
+    let path = create_dump("options");
+    let device = format!("file://{}", path.display());
+    let conn = PciLeech::new(&device, None, false).expect("unable to open file device");
+
+    // the file device is as large as the dump
+    assert_eq!(
+        conn.get_option(LcOption::CoreAddrMax)
+            .expect("unable to get leechcore option"),
+        LcOptionValue::U64(DUMP_SIZE as u64)
+    );
+
+    // the version is combined from the major, minor and revision options
+    let version = conn
+        .get_option(LcOption::CoreVersion)
+        .expect("unable to get leechcore option")
+        .as_version()
+        .expect("the core version is not a version triple");
+    assert_ne!(version, LcVersion::default());
+
+    // values of the wrong kind are rejected before they reach leechcore
+    for (option, value) in [
+        (LcOption::CoreVerbose, LcOptionValue::U64(1)),
+        (LcOption::CoreAddrMax, LcOptionValue::Bool(true)),
+        (LcOption::CoreVersion, LcOptionValue::Version(version)),
+    ]
+    .iter()
+    {
+        let err = conn
+            .set_option(*option, *value)
+            .expect_err("value of the wrong kind was accepted");
+        assert_eq!(err.1, ErrorKind::InvalidArgument);
+    }
+}
+
+#[test]
+fn reset_statistics() {
+    let path = create_dump("statistics");
+    let device = format!("file://{}", path.display());
+    let mut conn = PciLeech::new(&device, None, false).expect("unable to open file device");
+
+    let mut buf = [0u8; 8];
+    for _ in 0..4 {
+        conn.phys_view()
+            .read_raw_into(Address::from(0x1000), &mut buf)
+            .expect("unable to read physical memory");
+    }
+    let stats = conn.statistics().expect("unable to get statistics");
+    assert!(stats.read_scatter.count >= 4);
+
+    // the statistics of all clones are relative to the new baseline
+    conn.clone()
+        .reset_statistics()
+        .expect("unable to reset statistics");
+    let stats = conn.statistics().expect("unable to get statistics");
+    assert_eq!(stats.read_scatter.count, 0);
+}
+
+#[test]
+fn push_mem_map_to_leechcore() {
+    let path = create_dump("push");
+    let device = format!("file://{}", path.display());
+    let pushed_ranges = |conn: &PciLeech| {
+        conn.leechcore_mem_map().ok().map(|mem_map| {
+            mem_map
+                .into_vec()
+                .iter()
+                .map(|mapping| (mapping.base, mapping.size, mapping.real_base))
+                .collect::<Vec<_>>()
+        })
+    };
+
+    // the second range is located elsewhere on the device
+    let mappings = [
+        PhysicalMemoryMapping {
+            base: Address::from(0x1000),
+            size: 0x2000,
+            real_base: Address::from(0x1000),
+        },
+        PhysicalMemoryMapping {
+            base: Address::from(0x10000),
+            size: 0x2000,
+            real_base: Address::from(0x6000),
+        },
+    ];
+
+    // leechcore receives the ranges in the address space of the device
+    let mut conn = PciLeech::new(&device, None, false).expect("unable to open file device");
+    conn.set_mem_map(&mappings);
+    assert_eq!(
+        pushed_ranges(&conn),
+        Some(vec![
+            (Address::from(0x1000), 0x2000, Address::from(0x1000)),
+            (Address::from(0x6000), 0x2000, Address::from(0x6000)),
+        ])
+    );
+
+    // the memory map of leechcore is left untouched once pushing is disabled,
+    // a separate dump is used because leechcore shares the context of devices that are opened twice
+    let path = create_dump("push-disabled");
+    let device = format!("file://{}", path.display());
+    let mut conn = PciLeech::new(&device, None, false).expect("unable to open file device");
+    let initial = pushed_ranges(&conn);
+    conn.set_mem_map_push(false);
+    conn.set_mem_map(&mappings);
+    assert_eq!(pushed_ranges(&conn), initial);
+}
+
+#[test]
+fn report_missing_file_device() {
+    let path = std::env::temp_dir().join("memflow-pcileech-missing.raw");
+    let _ = std::fs::remove_file(&path);
+    let device = format!("file://{}", path.display());
+
+    // the reason for the failure is only printed by leechcore
+    let err = LcHandle::open(&device, None)
+        .err()
+        .expect("missing dump file was opened");
+    assert!(
+        matches!(err, LcCreateError::DeviceNotFound(_)),
+        "unexpected error: {}",
+        err
+    );
+    assert!(!err.message().is_empty());
+    assert!(err.to_string().contains(err.message()));
+
+    let err = PciLeech::new(&device, None, false)
+        .err()
+        .expect("missing dump file was opened");
+    assert_eq!(err.1, ErrorKind::NotFound);
+}
+
+#[test]
+fn reopen_file_device() {
+    let path = create_dump("reopen");
+    let device = format!("file://{}", path.display());
+
+    for _ in 0..64 {
+        let mut conn = PciLeech::new(&device, None, false).expect("unable to open file device");
+        let clone = conn.clone();
+
+        let mut buf = [0u8; 8];
+        conn.phys_view()
+            .read_raw_into(Address::from(0x1000), &mut buf)
+            .expect("unable to read physical memory");
+        assert_eq!(buf[0], (0x1000 % 251) as u8);
+
+        // the device is closed once the last clone is dropped
+        drop(conn);
+        drop(clone);
+    }
+}
+
+#[test]
+fn batched_small_writes() {
+    let path = create_dump("batched-writes");
+    let device = format!("file://file={},write=1", path.display());
+    let mut conn = PciLeech::new(&device, None, false).expect("unable to open file device");
+
+    // adjacent 1-7 byte values, several of them sharing the same aligned window
+    let window = Address::from(0x3000);
+    let mut expected = vec![0u8; 0x40];
+    conn.phys_view()
+        .read_raw_into(window, &mut expected)
+        .expect("unable to read physical memory");
+
+    let mut writes = Vec::new();
+    let mut offset = 1;
+    for len in (1..8usize).chain((1..8).rev()) {
+        let value = (0..len)
+            .map(|i| (0x80 + offset + i) as u8)
+            .collect::<Vec<_>>();
+        expected[offset..offset + len].copy_from_slice(&value);
+        writes.push((offset, value));
+        offset += len;
+    }
+
+    let list = writes
+        .iter()
+        .map(|(offset, value)| CTup2(window + *offset, CSliceRef::from(&value[..])))
+        .collect::<Vec<_>>();
+    conn.phys_view()
+        .write_raw_list(&list)
+        .expect("unable to write physical memory");
+
+    let mut current = vec![0u8; expected.len()];
+    conn.phys_view()
+        .read_raw_into(window, &mut current)
+        .expect("unable to read physical memory");
+    for (offset, value) in writes.iter() {
+        assert_eq!(
+            &current[*offset..*offset + value.len()],
+            &value[..],
+            "write of {} bytes at offset {offset:#x} was lost",
+            value.len()
+        );
+    }
+    assert_eq!(current, expected);
+}
+
+#[test]
+fn small_writes_keep_neighbours() {
+    let path = create_dump("rmw");
+    let device = format!("file://file={},write=1", path.display());
+    let mut conn = PciLeech::new(&device, None, false).expect("unable to open file device");
+
+    // the window covers the entire page as well as a few bytes of the neighbouring pages
+    let window = Address::from(0x2000 - 8);
+    let mut expected = vec![0u8; PAGE_SIZE + 16];
+    conn.phys_view()
+        .read_raw_into(window, &mut expected)
+        .expect("unable to read physical memory");
+
+    let mut current = vec![0u8; expected.len()];
+    for len in 1..8usize {
+        for offset in 0..PAGE_SIZE {
+            let start = 8 + offset;
+            let value = expected[start..start + len]
+                .iter()
+                .map(|b| !b)
+                .collect::<Vec<_>>();
+
+            conn.phys_view()
+                .write_raw(window + start, &value)
+                .expect("unable to write physical memory");
+            expected[start..start + len].copy_from_slice(&value);
+
+            conn.phys_view()
+                .read_raw_into(window, &mut current)
+                .expect("unable to read physical memory");
+            assert_eq!(
+                current, expected,
+                "write of {len} bytes at offset {offset:#x} changed neighbouring bytes"
+            );
+        }
+    }
+}
+
+#[test]
+fn export_mem_map_on_change() {
+    let path = create_dump("export");
+    let device = format!("file://{}", path.display());
+    let out = std::env::temp_dir().join("memflow-pcileech-export.toml");
+    let _ = std::fs::remove_file(&out);
+
+    // the export applies to all clones of the connector
+    let mut conn = PciLeech::new(&device, None, false).expect("unable to open file device");
+    conn.clone()
+        .export_mem_map(&out)
+        .expect("unable to export memory map");
+    assert!(!out.exists(), "no memory map is in effect yet");
+
+    // the os layer provides its memory map via `set_mem_map`
+    conn.set_mem_map(&[
+        PhysicalMemoryMapping {
+            base: Address::from(0x1000),
+            size: 0x3000,
+            real_base: Address::from(0x1000),
+        },
+        PhysicalMemoryMapping {
+            base: Address::from(0x8000),
+            size: 0x2000,
+            real_base: Address::from(0x6000),
+        },
+    ]);
+
+    let exported = std::fs::read_to_string(&out).expect("memory map was not exported");
+    assert_eq!(
+        exported,
+        "[[range]]\nbase=0x1000\nlength=0x3000\n\n[[range]]\nbase=0x8000\nlength=0x2000\nreal_base=0x6000\n\n"
+    );
+
+    // the exported file can be used to start the next session
+    let conn = PciLeech::with_mem_map_file(&device, None, &out, false)
+        .expect("unable to load exported memory map");
+    assert_eq!(conn.metadata().max_address, Address::from(0x9fff));
+}
+
+#[test]
+fn reject_overlapping_mem_map() {
+    let path = create_dump("overlap");
+    let device = format!("file://{}", path.display());
+    let mem_map = std::env::temp_dir().join("memflow-pcileech-overlap.toml");
+    std::fs::write(
+        &mem_map,
+        "[[range]]\nbase=0x4000\nlength=0x1000\n\n[[range]]\nbase=0x1000\nlength=0x2000\n\n[[range]]\nbase=0x2000\nlength=0x1000\n",
+    )
+    .expect("unable to create memory map file");
+
+    let err = PciLeech::with_mem_map_file(&device, None, &mem_map, false)
+        .err()
+        .expect("overlapping memory map was accepted");
+    assert_eq!(err.1, ErrorKind::Configuration);
+}
+
+#[test]
+fn mem_map_policy_combines_maps() {
+    let path = create_dump("policy");
+    let device = format!("file://{}", path.display());
+    let mapping = |base: u64, size: umem| PhysicalMemoryMapping {
+        base: Address::from(base),
+        size,
+        real_base: Address::from(base),
+    };
+
+    let mut conn = PciLeech::new(&device, None, false).expect("unable to open file device");
+    let clone = conn.clone();
+    conn.set_mem_map_policy(MemMapPolicy::Intersect);
+
+    // a coarse map provided by the user and a finer one provided by the os layer
+    conn.swap_mem_map(&[mapping(0x0, 0x8000)])
+        .expect("unable to swap memory map");
+    conn.set_mem_map(&[mapping(0x1000, 0x2000), mapping(0x6000, 0x4000)]);
+    assert_eq!(clone.metadata().max_address, Address::from(0x7fff));
+    assert_eq!(clone.metadata().real_size, 0x4000);
+
+    conn.set_mem_map_policy(MemMapPolicy::Union);
+    assert_eq!(clone.metadata().max_address, Address::from(0x9fff));
+    assert_eq!(clone.metadata().real_size, 0xa000);
+
+    // the map can be swapped at runtime from any clone
+    clone
+        .swap_mem_map(&[mapping(0x0, 0x1000)])
+        .expect("unable to swap memory map");
+    assert_eq!(conn.metadata().real_size, 0x7000);
+}
+
+#[test]
+fn probe_file_device() {
+    let path = create_dump("probe");
+    let device = format!("file://{}", path.display());
+    let conn = PciLeech::new(&device, None, false).expect("unable to open file device");
+
+    let mut last_progress = (0, 0);
+    let mem_map = conn
+        .probe_mem_map(|probed, total| last_progress = (probed, total))
+        .expect("unable to probe memory map");
+    assert_eq!(last_progress, (DUMP_SIZE as u64, DUMP_SIZE as u64));
+    assert_eq!(mem_map.max_address(), Address::from(DUMP_SIZE - 1));
+    assert_eq!(mem_map.real_size(), DUMP_SIZE as umem);
+}
+
+#[test]
+fn raw_dump_has_no_memory_info() {
+    let path = create_dump("memory-info");
+    let device = format!("file://{}", path.display());
+    let conn = PciLeech::new(&device, None, false).expect("unable to open file device");
+
+    // raw dumps do not have a header leechcore could take the information from
+    let memory_info = conn.memory_info().expect("unable to query memory info");
+    assert_eq!(memory_info, None);
+}
+
+#[test]
+fn metadata_from_device() {
+    let path = create_dump("metadata");
+
+    let conn = PciLeech::new(&format!("file://{}", path.display()), None, false)
+        .expect("unable to open file device");
+    let metadata = conn.metadata();
+    assert_eq!(metadata.max_address, Address::from(DUMP_SIZE - 1));
+    assert_eq!(metadata.real_size, DUMP_SIZE as umem);
+    assert!(metadata.readonly);
+    assert_eq!(metadata.ideal_batch_size, 128);
+
+    let conn = PciLeech::new(
+        &format!("file://file={},write=1", path.display()),
+        None,
+        false,
+    )
+    .expect("unable to open file device");
+    let metadata = conn.metadata();
+    assert_eq!(metadata.max_address, Address::from(DUMP_SIZE - 1));
+    assert_eq!(metadata.real_size, DUMP_SIZE as umem);
+    assert!(!metadata.readonly);
+}
+
+#[test]
+fn read_in_small_batches() {
+    let path = create_dump("batches");
+    let device = format!("file://{}", path.display());
+    let mut conn = PciLeech::new(&device, None, false).expect("unable to open file device");
+    conn.set_max_batch_pages(3)
+        .expect("unable to set maximum batch size");
+
+    // an unaligned read spanning more pages than fit into a single batch
+    let mut buf = vec![0u8; DUMP_SIZE - 0x1003];
+    conn.phys_view()
+        .read_raw_into(Address::from(0x1001), &mut buf)
+        .expect("unable to read physical memory");
+    let expected = (0x1001..0x1001 + buf.len())
+        .map(|i| (i % 251) as u8)
+        .collect::<Vec<_>>();
+    assert_eq!(buf, expected);
+
+    assert!(conn.set_max_batch_pages(0).is_err());
+}
+
+#[test]
+fn coalesce_small_reads() {
+    let path = create_dump("coalesce");
+    let device = format!("file://{}", path.display());
+    let mut conn = PciLeech::new(&device, None, false).expect("unable to open file device");
+
+    // overlapping and unaligned reads sharing a page mixed with reads on separate pages
+    let addrs = [
+        0x2004usize,
+        0x5000,
+        0x2000,
+        0x2ffd,
+        0x2006,
+        0x3008,
+        0x2004,
+        0x5800,
+    ];
+    let mut bufs = vec![[0u8; 3]; addrs.len()];
+    let mut reads = addrs
+        .iter()
+        .zip(bufs.iter_mut())
+        .map(|(&addr, buf)| CTup2(Address::from(addr as umem), CSliceMut::from(&mut buf[..])))
+        .collect::<Vec<_>>();
+    conn.phys_view()
+        .read_raw_list(&mut reads)
+        .expect("unable to read physical memory");
+    drop(reads);
+
+    for (addr, buf) in addrs.iter().zip(bufs.iter()) {
+        let expected = (*addr..*addr + 3)
+            .map(|i| (i % 251) as u8)
+            .collect::<Vec<_>>();
+        assert_eq!(
+            &buf[..],
+            &expected[..],
+            "read at {addr:#x} returned wrong data"
+        );
+    }
+}
+
+#[test]
+fn bulk_read_falls_back_to_pages() {
+    let path = create_dump("bulk-read");
+    let device = format!("file://{}", path.display());
+    let mut conn = PciLeech::new(&device, None, false).expect("unable to open file device");
+    conn.set_bulk_min_pages(Some(4));
+
+    let expected = (0..DUMP_SIZE).map(|i| (i % 251) as u8).collect::<Vec<_>>();
+    let mut buf = vec![0u8; DUMP_SIZE];
+    conn.phys_view()
+        .read_raw_into(Address::NULL, &mut buf)
+        .expect("unable to read physical memory");
+    assert_eq!(buf, expected);
+
+    // the second half of the block is beyond the end of the dump, the first half still has to be read
+    let mut buf = vec![0u8; DUMP_SIZE * 2];
+    assert!(conn
+        .phys_view()
+        .read_raw_into(Address::NULL, &mut buf)
+        .is_err());
+    assert_eq!(&buf[..DUMP_SIZE], &expected[..]);
+}
+
+#[test]
+fn bulk_write_round_trip() {
+    let path = create_dump("bulk-write");
+    let device = format!("file://file={},write=1", path.display());
+    let mut conn = PciLeech::new(&device, None, false).expect("unable to open file device");
+    conn.set_bulk_min_pages(Some(4));
+
+    let data = (0..PAGE_SIZE * 8)
+        .map(|i| (i % 13) as u8)
+        .collect::<Vec<_>>();
+    conn.phys_view()
+        .write_raw(Address::from(0x4000), &data)
+        .expect("unable to write physical memory");
+
+    let mut buf = vec![0u8; data.len()];
+    conn.phys_view()
+        .read_raw_into(Address::from(0x4000), &mut buf)
+        .expect("unable to read physical memory");
+    assert_eq!(buf, data);
+}
+
+#[test]
+fn retry_failed_pages() {
+    let path = create_dump("retry");
+    let device = format!("file://{}", path.display());
+    let mut conn = PciLeech::new(&device, None, false).expect("unable to open file device");
+    conn.set_retries(2, Duration::from_millis(1));
+
+    // the second half of the memory map is beyond the end of the dump and can never be read
+    conn.swap_mem_map(&[PhysicalMemoryMapping {
+        base: Address::NULL,
+        size: (DUMP_SIZE * 2) as umem,
+        real_base: Address::NULL,
+    }])
+    .expect("unable to swap memory map");
+
+    let mut buf = vec![0u8; PAGE_SIZE * 2];
+    assert!(conn
+        .phys_view()
+        .read_raw_into(Address::from(DUMP_SIZE - PAGE_SIZE), &mut buf)
+        .is_err());
+
+    let stats = conn.retry_statistics();
+    assert_eq!(stats.retried_pages, 2);
+    assert_eq!(stats.recovered_pages, 0);
+    assert_eq!(stats.failed_pages, 1);
+}
+
+#[test]
+fn reject_invalid_fpga_args() {
+    let path = create_dump("fpga-args");
+    for arg in [
+        "fpga-algo-tiny=2",
+        "fpga-delay-read=fast",
+        "fpga-delay-write=2000000",
+        "fpga-max-size-rx=0",
+    ]
+    .iter()
+    {
+        let args = format!(":device=\"file://{}\":{}", path.display(), arg)
+            .parse::<ConnectorArgs>()
+            .expect("unable to parse connector arguments");
+        assert!(
+            memflow_pcileech::create_connector(&args).is_err(),
+            "invalid argument `{}` was accepted",
+            arg
+        );
+    }
+}